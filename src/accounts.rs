@@ -0,0 +1,745 @@
+// Account contexts of every instruction, checking owners, signers, writability and
+// addresses before the processor touches any of them
+use crate::error::IWRError;
+use crate::state::State;
+use crate::{
+    get_lp_mint_address_with_seed, get_mint_address_with_seed, get_native_pool_address_with_seed,
+    get_oracle_address, get_oracle_address_with_seed, get_quote_vault_address_with_seed,
+    get_state_address_with_seed, get_token_pool_address_with_seed,
+};
+use solana_program::account_info::{next_account_info, AccountInfo};
+use solana_program::msg;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use solana_program::{system_program, sysvar};
+use spl_associated_token_account::get_associated_token_address;
+
+// ----------------------------------------------------------------------------
+// initialize
+
+pub(crate) struct InitializeAccounts<'a, 'b> {
+    pub payer_info: &'a AccountInfo<'b>,
+    pub state_info: &'a AccountInfo<'b>,
+    pub mint_info: &'a AccountInfo<'b>,
+    pub token_pool_info: &'a AccountInfo<'b>,
+    pub quote_pool_info: &'a AccountInfo<'b>,
+    pub lp_mint_info: &'a AccountInfo<'b>,
+    pub quote_mint_info: &'a AccountInfo<'b>,
+    pub payer_quote_info: &'a AccountInfo<'b>,
+    pub system_program_info: &'a AccountInfo<'b>,
+    pub token_program_info: &'a AccountInfo<'b>,
+    pub rent_sysvar_info: &'a AccountInfo<'b>,
+    pub state_bump_seed: u8,
+    pub mint_bump_seed: u8,
+    pub token_pool_bump_seed: u8,
+    pub quote_pool_bump_seed: u8,
+    pub quote_pool_seed: &'static [u8],
+    pub lp_mint_bump_seed: u8,
+}
+
+impl<'a, 'b> InitializeAccounts<'a, 'b> {
+    pub fn load(accounts: &'a [AccountInfo<'b>], pool_index: u64) -> Result<Self, ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let payer_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_pool_info = next_account_info(account_info_iter)?;
+        let quote_pool_info = next_account_info(account_info_iter)?;
+        let lp_mint_info = next_account_info(account_info_iter)?;
+        let quote_mint_info = next_account_info(account_info_iter)?;
+        let payer_quote_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+        check_signer(payer_info, "payer")?;
+        check_program(system_program_info, &system_program::id(), "system program")?;
+        check_program(token_program_info, &spl_token::id(), "token program")?;
+        check_address(rent_sysvar_info, &sysvar::rent::id(), "rent sysvar")?;
+
+        // the native mint is only a marker, SPL quote mints have to be real
+        if *quote_mint_info.key != spl_token::native_mint::id() {
+            check_owner(quote_mint_info, &spl_token::id(), "quote mint")?;
+        }
+
+        let (state_address, state_bump_seed) =
+            get_state_address_with_seed(payer_info.key, pool_index);
+        let (mint_address, mint_bump_seed) = get_mint_address_with_seed(payer_info.key, pool_index);
+        let (token_pool_address, token_pool_bump_seed) =
+            get_token_pool_address_with_seed(payer_info.key, pool_index);
+        let (quote_pool_address, quote_pool_bump_seed, quote_pool_seed) =
+            get_quote_pool_address_with_seed(payer_info.key, pool_index, quote_mint_info.key);
+        let (lp_mint_address, lp_mint_bump_seed) =
+            get_lp_mint_address_with_seed(payer_info.key, pool_index);
+
+        check_address(state_info, &state_address, "state")?;
+        check_address(mint_info, &mint_address, "mint")?;
+        check_address(token_pool_info, &token_pool_address, "token pool")?;
+        check_address(quote_pool_info, &quote_pool_address, "quote pool")?;
+        check_address(lp_mint_info, &lp_mint_address, "lp mint")?;
+
+        for (info, name) in [
+            (payer_info, "payer"),
+            (state_info, "state"),
+            (mint_info, "mint"),
+            (token_pool_info, "token pool"),
+            (quote_pool_info, "quote pool"),
+            (lp_mint_info, "lp mint"),
+            (payer_quote_info, "payer quote"),
+        ] {
+            check_writable(info, name)?;
+        }
+
+        Ok(Self {
+            payer_info,
+            state_info,
+            mint_info,
+            token_pool_info,
+            quote_pool_info,
+            lp_mint_info,
+            quote_mint_info,
+            payer_quote_info,
+            system_program_info,
+            token_program_info,
+            rent_sysvar_info,
+            state_bump_seed,
+            mint_bump_seed,
+            token_pool_bump_seed,
+            quote_pool_bump_seed,
+            quote_pool_seed,
+            lp_mint_bump_seed,
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+// swaps
+
+/// Accounts shared by every buy and sell, the customer always signing
+pub(crate) struct SwapAccounts<'a, 'b> {
+    pub creator_info: &'a AccountInfo<'b>,
+    pub state_info: &'a AccountInfo<'b>,
+    pub mint_info: &'a AccountInfo<'b>,
+    pub token_pool_info: &'a AccountInfo<'b>,
+    pub quote_pool_info: &'a AccountInfo<'b>,
+    pub beneficiary_info: &'a AccountInfo<'b>,
+    pub customer_info: &'a AccountInfo<'b>,
+    pub customer_token_associated_info: &'a AccountInfo<'b>,
+    pub customer_quote_info: &'a AccountInfo<'b>,
+    pub system_program_info: &'a AccountInfo<'b>,
+    pub token_program_info: &'a AccountInfo<'b>,
+    /// Left alone until someone sends `InitializeOracle`
+    pub oracle_info: &'a AccountInfo<'b>,
+    pub mint_bump_seed: u8,
+    pub token_pool_bump_seed: u8,
+    pub quote_pool_bump_seed: u8,
+    pub quote_pool_seed: &'static [u8],
+}
+
+impl<'a, 'b> SwapAccounts<'a, 'b> {
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Self, State), ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let creator_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_pool_info = next_account_info(account_info_iter)?;
+        let quote_pool_info = next_account_info(account_info_iter)?;
+        let beneficiary_info = next_account_info(account_info_iter)?;
+        let customer_info = next_account_info(account_info_iter)?;
+        let customer_token_associated_info = next_account_info(account_info_iter)?;
+        let customer_quote_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let _rent_sysvar_info = next_account_info(account_info_iter)?;
+        let oracle_info = next_account_info(account_info_iter)?;
+
+        check_signer(customer_info, "customer")?;
+        check_program(system_program_info, &system_program::id(), "system program")?;
+        check_program(token_program_info, &spl_token::id(), "token program")?;
+
+        let state = load_state(program_id, state_info)?;
+
+        // the pool index is only trusted once the state address derived from it matches
+        check_address(creator_info, &state.creator, "creator")?;
+
+        let (mint_address, mint_bump_seed) =
+            get_mint_address_with_seed(&state.creator, state.pool_index);
+        let (token_pool_address, token_pool_bump_seed) =
+            get_token_pool_address_with_seed(&state.creator, state.pool_index);
+        let (quote_pool_address, quote_pool_bump_seed, quote_pool_seed) =
+            get_quote_pool_address_with_seed(&state.creator, state.pool_index, &state.quote_mint);
+
+        check_address(mint_info, &mint_address, "mint")?;
+        check_address(token_pool_info, &token_pool_address, "token pool")?;
+        check_address(quote_pool_info, &quote_pool_address, "quote pool")?;
+        check_address(oracle_info, &get_oracle_address(state_info.key), "oracle")?;
+        check_address(
+            customer_token_associated_info,
+            &get_associated_token_address(customer_info.key, &mint_address),
+            "customer token account",
+        )?;
+
+        // fees may only go to the beneficiary recorded by the pool authority
+        if *beneficiary_info.key != state.beneficiary {
+            msg!("Error: beneficiary mismatch");
+            return Err(IWRError::IncorrectBeneficiary.into());
+        }
+
+        for (info, name) in [
+            (state_info, "state"),
+            (token_pool_info, "token pool"),
+            (quote_pool_info, "quote pool"),
+            (beneficiary_info, "beneficiary"),
+            (customer_info, "customer"),
+            (customer_token_associated_info, "customer token account"),
+            (customer_quote_info, "customer quote"),
+            (oracle_info, "oracle"),
+        ] {
+            check_writable(info, name)?;
+        }
+
+        Ok((
+            Self {
+                creator_info,
+                state_info,
+                mint_info,
+                token_pool_info,
+                quote_pool_info,
+                beneficiary_info,
+                customer_info,
+                customer_token_associated_info,
+                customer_quote_info,
+                system_program_info,
+                token_program_info,
+                oracle_info,
+                mint_bump_seed,
+                token_pool_bump_seed,
+                quote_pool_bump_seed,
+                quote_pool_seed,
+            },
+            state,
+        ))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// liquidity
+
+/// Accounts of both `AddLiquidity` and `RemoveLiquidity`, the provider always signing
+pub(crate) struct LiquidityAccounts<'a, 'b> {
+    pub state_info: &'a AccountInfo<'b>,
+    pub token_pool_info: &'a AccountInfo<'b>,
+    pub quote_pool_info: &'a AccountInfo<'b>,
+    pub lp_mint_info: &'a AccountInfo<'b>,
+    pub provider_info: &'a AccountInfo<'b>,
+    pub provider_token_associated_info: &'a AccountInfo<'b>,
+    pub provider_lp_associated_info: &'a AccountInfo<'b>,
+    pub provider_quote_info: &'a AccountInfo<'b>,
+    pub system_program_info: &'a AccountInfo<'b>,
+    pub token_program_info: &'a AccountInfo<'b>,
+    pub token_pool_bump_seed: u8,
+    pub quote_pool_bump_seed: u8,
+    pub quote_pool_seed: &'static [u8],
+    pub lp_mint_bump_seed: u8,
+}
+
+impl<'a, 'b> LiquidityAccounts<'a, 'b> {
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Self, State), ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let state_info = next_account_info(account_info_iter)?;
+        let token_pool_info = next_account_info(account_info_iter)?;
+        let quote_pool_info = next_account_info(account_info_iter)?;
+        let lp_mint_info = next_account_info(account_info_iter)?;
+        let provider_info = next_account_info(account_info_iter)?;
+        let provider_token_associated_info = next_account_info(account_info_iter)?;
+        let provider_lp_associated_info = next_account_info(account_info_iter)?;
+        let provider_quote_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        check_signer(provider_info, "provider")?;
+        check_program(system_program_info, &system_program::id(), "system program")?;
+        check_program(token_program_info, &spl_token::id(), "token program")?;
+
+        let state = load_state(program_id, state_info)?;
+
+        let (mint_address, _) = get_mint_address_with_seed(&state.creator, state.pool_index);
+        let (token_pool_address, token_pool_bump_seed) =
+            get_token_pool_address_with_seed(&state.creator, state.pool_index);
+        let (quote_pool_address, quote_pool_bump_seed, quote_pool_seed) =
+            get_quote_pool_address_with_seed(&state.creator, state.pool_index, &state.quote_mint);
+        let (lp_mint_address, lp_mint_bump_seed) =
+            get_lp_mint_address_with_seed(&state.creator, state.pool_index);
+
+        check_address(token_pool_info, &token_pool_address, "token pool")?;
+        check_address(quote_pool_info, &quote_pool_address, "quote pool")?;
+        check_address(lp_mint_info, &lp_mint_address, "lp mint")?;
+        check_address(
+            provider_token_associated_info,
+            &get_associated_token_address(provider_info.key, &mint_address),
+            "provider token account",
+        )?;
+        check_address(
+            provider_lp_associated_info,
+            &get_associated_token_address(provider_info.key, &lp_mint_address),
+            "provider lp account",
+        )?;
+
+        for (info, name) in [
+            (state_info, "state"),
+            (token_pool_info, "token pool"),
+            (quote_pool_info, "quote pool"),
+            (lp_mint_info, "lp mint"),
+            (provider_info, "provider"),
+            (provider_token_associated_info, "provider token account"),
+            (provider_lp_associated_info, "provider lp account"),
+            (provider_quote_info, "provider quote"),
+        ] {
+            check_writable(info, name)?;
+        }
+
+        Ok((
+            Self {
+                state_info,
+                token_pool_info,
+                quote_pool_info,
+                lp_mint_info,
+                provider_info,
+                provider_token_associated_info,
+                provider_lp_associated_info,
+                provider_quote_info,
+                system_program_info,
+                token_program_info,
+                token_pool_bump_seed,
+                quote_pool_bump_seed,
+                quote_pool_seed,
+                lp_mint_bump_seed,
+            },
+            state,
+        ))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// authority
+
+/// Accounts of `Graduate` and `Skim`, moving reserves out on behalf of the pool authority
+pub(crate) struct WithdrawAccounts<'a, 'b> {
+    pub state_info: &'a AccountInfo<'b>,
+    pub token_pool_info: &'a AccountInfo<'b>,
+    pub quote_pool_info: &'a AccountInfo<'b>,
+    pub base_destination_info: &'a AccountInfo<'b>,
+    pub quote_destination_info: &'a AccountInfo<'b>,
+    pub token_program_info: &'a AccountInfo<'b>,
+    pub token_pool_bump_seed: u8,
+    pub quote_pool_bump_seed: u8,
+    pub quote_pool_seed: &'static [u8],
+}
+
+impl<'a, 'b> WithdrawAccounts<'a, 'b> {
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Self, State), ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let authority_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let token_pool_info = next_account_info(account_info_iter)?;
+        let quote_pool_info = next_account_info(account_info_iter)?;
+        let base_destination_info = next_account_info(account_info_iter)?;
+        let quote_destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        check_program(token_program_info, &spl_token::id(), "token program")?;
+
+        let state = load_authorized_state(program_id, authority_info, state_info)?;
+
+        let (token_pool_address, token_pool_bump_seed) =
+            get_token_pool_address_with_seed(&state.creator, state.pool_index);
+        let (quote_pool_address, quote_pool_bump_seed, quote_pool_seed) =
+            get_quote_pool_address_with_seed(&state.creator, state.pool_index, &state.quote_mint);
+
+        check_address(token_pool_info, &token_pool_address, "token pool")?;
+        check_address(quote_pool_info, &quote_pool_address, "quote pool")?;
+
+        for (info, name) in [
+            (state_info, "state"),
+            (token_pool_info, "token pool"),
+            (quote_pool_info, "quote pool"),
+            (base_destination_info, "base destination"),
+            (quote_destination_info, "quote destination"),
+        ] {
+            check_writable(info, name)?;
+        }
+
+        Ok((
+            Self {
+                state_info,
+                token_pool_info,
+                quote_pool_info,
+                base_destination_info,
+                quote_destination_info,
+                token_program_info,
+                token_pool_bump_seed,
+                quote_pool_bump_seed,
+                quote_pool_seed,
+            },
+            state,
+        ))
+    }
+}
+
+/// Accounts of `ClosePool`, the quote destination only used by pools with an SPL quote
+pub(crate) struct CloseAccounts<'a, 'b> {
+    pub state_info: &'a AccountInfo<'b>,
+    pub mint_info: &'a AccountInfo<'b>,
+    pub token_pool_info: &'a AccountInfo<'b>,
+    pub quote_pool_info: &'a AccountInfo<'b>,
+    pub lp_mint_info: &'a AccountInfo<'b>,
+    pub oracle_info: &'a AccountInfo<'b>,
+    pub destination_info: &'a AccountInfo<'b>,
+    pub quote_destination_info: &'a AccountInfo<'b>,
+    pub token_program_info: &'a AccountInfo<'b>,
+    pub token_pool_bump_seed: u8,
+    pub quote_pool_bump_seed: u8,
+    pub quote_pool_seed: &'static [u8],
+}
+
+impl<'a, 'b> CloseAccounts<'a, 'b> {
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Self, State), ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let authority_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_pool_info = next_account_info(account_info_iter)?;
+        let quote_pool_info = next_account_info(account_info_iter)?;
+        let lp_mint_info = next_account_info(account_info_iter)?;
+        let oracle_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let quote_destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        check_program(token_program_info, &spl_token::id(), "token program")?;
+
+        let state = load_authorized_state(program_id, authority_info, state_info)?;
+
+        let (mint_address, _) = get_mint_address_with_seed(&state.creator, state.pool_index);
+        let (token_pool_address, token_pool_bump_seed) =
+            get_token_pool_address_with_seed(&state.creator, state.pool_index);
+        let (quote_pool_address, quote_pool_bump_seed, quote_pool_seed) =
+            get_quote_pool_address_with_seed(&state.creator, state.pool_index, &state.quote_mint);
+        let (lp_mint_address, _) = get_lp_mint_address_with_seed(&state.creator, state.pool_index);
+
+        check_address(mint_info, &mint_address, "mint")?;
+        check_address(token_pool_info, &token_pool_address, "token pool")?;
+        check_address(quote_pool_info, &quote_pool_address, "quote pool")?;
+        check_address(lp_mint_info, &lp_mint_address, "lp mint")?;
+        check_address(oracle_info, &get_oracle_address(state_info.key), "oracle")?;
+
+        for (info, name) in [
+            (state_info, "state"),
+            (mint_info, "mint"),
+            (token_pool_info, "token pool"),
+            (quote_pool_info, "quote pool"),
+            (oracle_info, "oracle"),
+            (destination_info, "destination"),
+            (quote_destination_info, "quote destination"),
+        ] {
+            check_writable(info, name)?;
+        }
+
+        Ok((
+            Self {
+                state_info,
+                mint_info,
+                token_pool_info,
+                quote_pool_info,
+                lp_mint_info,
+                oracle_info,
+                destination_info,
+                quote_destination_info,
+                token_program_info,
+                token_pool_bump_seed,
+                quote_pool_bump_seed,
+                quote_pool_seed,
+            },
+            state,
+        ))
+    }
+}
+
+/// Accounts of the instructions only changing the state, signed by `signer_info`
+pub(crate) struct AdminAccounts<'a, 'b> {
+    pub signer_info: &'a AccountInfo<'b>,
+    pub state_info: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> AdminAccounts<'a, 'b> {
+    /// Requires the signer to be the current authority
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Self, State), ProgramError> {
+        let accounts = Self::load_unchecked(accounts)?;
+        let state = load_authorized_state(program_id, accounts.signer_info, accounts.state_info)?;
+
+        Ok((accounts, state))
+    }
+
+    /// Leaves it to the caller to decide who may sign
+    pub fn load_unchecked(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let signer_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+
+        check_signer(signer_info, "signer")?;
+        check_writable(state_info, "state")?;
+
+        Ok(Self {
+            signer_info,
+            state_info,
+        })
+    }
+}
+
+/// Accounts of `Quote`, read-only, the quote pool backing the payout of sells
+pub(crate) struct QuoteAccounts<'a, 'b> {
+    pub quote_pool_info: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> QuoteAccounts<'a, 'b> {
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Self, State), ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let state_info = next_account_info(account_info_iter)?;
+        let quote_pool_info = next_account_info(account_info_iter)?;
+
+        let state = load_state(program_id, state_info)?;
+
+        let (quote_pool_address, _, _) =
+            get_quote_pool_address_with_seed(&state.creator, state.pool_index, &state.quote_mint);
+        check_address(quote_pool_info, &quote_pool_address, "quote pool")?;
+
+        Ok((Self { quote_pool_info }, state))
+    }
+}
+
+/// Accounts of `InitializeOracle`, paid for by any signer
+pub(crate) struct OracleAccounts<'a, 'b> {
+    pub payer_info: &'a AccountInfo<'b>,
+    pub state_info: &'a AccountInfo<'b>,
+    pub oracle_info: &'a AccountInfo<'b>,
+    pub system_program_info: &'a AccountInfo<'b>,
+    pub oracle_bump_seed: u8,
+}
+
+impl<'a, 'b> OracleAccounts<'a, 'b> {
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(Self, State), ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let payer_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let oracle_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        check_signer(payer_info, "payer")?;
+        check_program(system_program_info, &system_program::id(), "system program")?;
+
+        let state = load_state(program_id, state_info)?;
+
+        let (oracle_address, oracle_bump_seed) = get_oracle_address_with_seed(state_info.key);
+        check_address(oracle_info, &oracle_address, "oracle")?;
+
+        check_writable(payer_info, "payer")?;
+        check_writable(oracle_info, "oracle")?;
+
+        Ok((
+            Self {
+                payer_info,
+                state_info,
+                oracle_info,
+                system_program_info,
+                oracle_bump_seed,
+            },
+            state,
+        ))
+    }
+}
+
+/// Accounts of `MigrateState`, whose state can't be decoded until it's migrated
+pub(crate) struct MigrateAccounts<'a, 'b> {
+    pub authority_info: &'a AccountInfo<'b>,
+    pub state_info: &'a AccountInfo<'b>,
+    pub mint_info: &'a AccountInfo<'b>,
+    pub lp_mint_info: &'a AccountInfo<'b>,
+    pub system_program_info: &'a AccountInfo<'b>,
+    pub token_program_info: &'a AccountInfo<'b>,
+    pub rent_sysvar_info: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> MigrateAccounts<'a, 'b> {
+    pub fn load(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<Self, ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let authority_info = next_account_info(account_info_iter)?;
+        let state_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let lp_mint_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let rent_sysvar_info = next_account_info(account_info_iter)?;
+
+        check_signer(authority_info, "authority")?;
+        check_owner(state_info, program_id, "state")?;
+        check_program(system_program_info, &system_program::id(), "system program")?;
+        check_program(token_program_info, &spl_token::id(), "token program")?;
+        check_address(rent_sysvar_info, &sysvar::rent::id(), "rent sysvar")?;
+
+        // the authority pays for the state growing and for the LP mint of older pools
+        check_writable(authority_info, "authority")?;
+        check_writable(state_info, "state")?;
+        check_writable(lp_mint_info, "lp mint")?;
+
+        Ok(Self {
+            authority_info,
+            state_info,
+            mint_info,
+            lp_mint_info,
+            system_program_info,
+            token_program_info,
+            rent_sysvar_info,
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+// checks
+
+/// Reads the pool state, making sure it's ours and lives at the address derived from
+/// its creator
+pub(crate) fn load_state(
+    program_id: &Pubkey,
+    state_info: &AccountInfo,
+) -> Result<State, ProgramError> {
+    check_owner(state_info, program_id, "state")?;
+
+    // the version leads, so other layouts are caught before decoding
+    match state_info.data.borrow().first() {
+        Some(&State::CURRENT_VERSION) => {}
+        None | Some(&State::UNINITIALIZED_VERSION) => {
+            msg!("Error: state is not initialized");
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Some(version) => {
+            msg!("Error: state version {} is not supported", version);
+            return Err(IWRError::UnsupportedStateVersion.into());
+        }
+    }
+
+    // sized exactly before later fields such as the oracle were appended
+    if state_info.data_len() < State::LEN {
+        msg!("Error: state is shorter than the current layout and needs migrating");
+        return Err(IWRError::UnsupportedStateVersion.into());
+    }
+
+    let state = State::unpack(&state_info.data.borrow())?;
+
+    let (state_address, _) = get_state_address_with_seed(&state.creator, state.pool_index);
+    check_address(state_info, &state_address, "state")?;
+
+    Ok(state)
+}
+
+/// Same as `load_state`, additionally requiring the signature of the current authority
+pub(crate) fn load_authorized_state(
+    program_id: &Pubkey,
+    authority_info: &AccountInfo,
+    state_info: &AccountInfo,
+) -> Result<State, ProgramError> {
+    check_signer(authority_info, "authority")?;
+
+    let state = load_state(program_id, state_info)?;
+
+    if state.authority != *authority_info.key {
+        msg!("Error: incorrect authority");
+        return Err(IWRError::IncorrectAuthority.into());
+    }
+
+    Ok(state)
+}
+
+/// Address of the account holding a pool's quote along with its bump and seed: the
+/// native pool for lamport-quoted pools, the quote vault for SPL-quoted ones
+pub(crate) fn get_quote_pool_address_with_seed(
+    creator: &Pubkey,
+    pool_index: u64,
+    quote_mint: &Pubkey,
+) -> (Pubkey, u8, &'static [u8]) {
+    if *quote_mint == spl_token::native_mint::id() {
+        let (address, bump_seed) = get_native_pool_address_with_seed(creator, pool_index);
+        (address, bump_seed, br"native-pool")
+    } else {
+        let (address, bump_seed) = get_quote_vault_address_with_seed(creator, pool_index);
+        (address, bump_seed, br"quote-vault")
+    }
+}
+
+fn check_signer(info: &AccountInfo, name: &str) -> Result<(), IWRError> {
+    if !info.is_signer {
+        msg!("Error: {} signature missing", name);
+        return Err(IWRError::MissingSignature);
+    }
+
+    Ok(())
+}
+
+fn check_writable(info: &AccountInfo, name: &str) -> Result<(), IWRError> {
+    if !info.is_writable {
+        msg!("Error: {} account is not writable", name);
+        return Err(IWRError::AccountNotWritable);
+    }
+
+    Ok(())
+}
+
+fn check_owner(info: &AccountInfo, owner: &Pubkey, name: &str) -> Result<(), IWRError> {
+    if info.owner != owner {
+        msg!("Error: {} account is owned by {}", name, info.owner);
+        return Err(IWRError::IncorrectOwner);
+    }
+
+    Ok(())
+}
+
+fn check_program(info: &AccountInfo, program_id: &Pubkey, name: &str) -> Result<(), IWRError> {
+    if info.key != program_id {
+        msg!("Error: {} mismatch, got {}", name, info.key);
+        return Err(IWRError::IncorrectProgram);
+    }
+
+    Ok(())
+}
+
+fn check_address(info: &AccountInfo, address: &Pubkey, name: &str) -> Result<(), IWRError> {
+    if info.key != address {
+        msg!("Error: {} address mismatch", name);
+        return Err(IWRError::IncorrectAddress);
+    }
+
+    Ok(())
+}
@@ -1,3 +1,8 @@
+use crate::error::IWRError;
+use crate::math::{
+    add, div, div_ceil, exp_wad, isqrt, ln_wad, mul, mul_div_ceil, mul_div_floor, sub, WAD,
+};
+use crate::BASE_UNIT;
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 
 #[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
@@ -7,38 +12,122 @@ pub struct Balance {
 }
 
 impl Balance {
-    pub fn calculate_fee_of(&self, quote_amount: u128) -> u128 {
-        30 * quote_amount / 10000
+    pub fn calculate_base_for_quote_amount(&self, quote_amount: u128) -> Result<u128, IWRError> {
+        mul_div_floor(self.base, quote_amount, add(self.quote, quote_amount)?)
     }
 
-    pub fn calculate_base_for_quote_amount(&self, quote_amount: u128) -> u128 {
-        (self.base * quote_amount) / (self.quote + quote_amount)
+    pub fn calculate_quote_for_base_amount(&self, base_amount: u128) -> Result<u128, IWRError> {
+        mul_div_floor(self.quote, base_amount, add(self.base, base_amount)?)
     }
 
-    pub fn calculate_quote_for_base_amount(&self, base_amount: u128) -> u128 {
-        (self.quote * base_amount) / (self.base + base_amount)
+    /// Quote required to take exactly `base_amount` out of the pool, rounded up
+    pub fn calculate_quote_for_base_out(&self, base_amount: u128) -> Result<u128, IWRError> {
+        mul_div_ceil(self.quote, base_amount, sub(self.base, base_amount)?)
     }
 
-    pub fn apply_buy_base(&mut self, base_amount: u128) {
-        self.apply_buy_base_for_quote(self.calculate_quote_for_base_amount(base_amount))
+    /// Base required to take exactly `quote_amount` out of the pool, rounded up
+    pub fn calculate_base_for_quote_out(&self, quote_amount: u128) -> Result<u128, IWRError> {
+        mul_div_ceil(self.base, quote_amount, sub(self.quote, quote_amount)?)
     }
 
-    pub fn apply_buy_base_for_quote(&mut self, quote_amount: u128) {
-        let base_amount = self.calculate_base_for_quote_amount(quote_amount);
+    /// Moves `base_amount` out of the pool and `quote_amount` into it
+    pub fn apply_buy(&mut self, base_amount: u128, quote_amount: u128) -> Result<(), IWRError> {
+        let base = sub(self.base, base_amount)?;
+        let quote = add(self.quote, quote_amount)?;
 
-        self.quote += quote_amount;
-        self.base -= base_amount;
+        self.base = base;
+        self.quote = quote;
+
+        Ok(())
+    }
+
+    /// Moves `base_amount` into the pool and `quote_amount` out of it
+    pub fn apply_sell(&mut self, base_amount: u128, quote_amount: u128) -> Result<(), IWRError> {
+        let base = add(self.base, base_amount)?;
+        let quote = sub(self.quote, quote_amount)?;
+
+        self.base = base;
+        self.quote = quote;
+
+        Ok(())
+    }
+
+    pub fn apply_buy_base(&mut self, base_amount: u128) -> Result<(), IWRError> {
+        let quote_amount = self.calculate_quote_for_base_out(base_amount)?;
+
+        self.apply_buy(base_amount, quote_amount)
+    }
+
+    pub fn apply_buy_base_for_quote(&mut self, quote_amount: u128) -> Result<(), IWRError> {
+        let base_amount = self.calculate_base_for_quote_amount(quote_amount)?;
+
+        self.apply_buy(base_amount, quote_amount)
+    }
+
+    pub fn apply_sell_base(&mut self, base_amount: u128) -> Result<(), IWRError> {
+        let quote_amount = self.calculate_quote_for_base_amount(base_amount)?;
+
+        self.apply_sell(base_amount, quote_amount)
+    }
+
+    pub fn apply_sell_base_for_quote(&mut self, quote_amount: u128) -> Result<(), IWRError> {
+        let base_amount = self.calculate_base_for_quote_out(quote_amount)?;
+
+        self.apply_sell(base_amount, quote_amount)
+    }
+
+    /// Base matching a deposit of `quote_amount` at the current ratio, rounded up
+    pub fn calculate_base_for_liquidity(&self, quote_amount: u128) -> Result<u128, IWRError> {
+        mul_div_ceil(self.base, quote_amount, self.quote)
+    }
+
+    /// Shares out of `total_shares` earned by depositing `quote_amount`, rounded down
+    pub fn calculate_shares_for_liquidity(
+        &self,
+        quote_amount: u128,
+        total_shares: u128,
+    ) -> Result<u128, IWRError> {
+        mul_div_floor(quote_amount, total_shares, self.quote)
+    }
+
+    /// Base and quote backing `shares` out of `total_shares`, rounded down
+    pub fn calculate_liquidity_for_shares(
+        &self,
+        shares: u128,
+        total_shares: u128,
+    ) -> Result<(u128, u128), IWRError> {
+        Ok((
+            mul_div_floor(self.base, shares, total_shares)?,
+            mul_div_floor(self.quote, shares, total_shares)?,
+        ))
     }
 
-    pub fn apply_sell_base(&mut self, base_amount: u128) {
-        let quote_amount = self.calculate_quote_for_base_amount(base_amount);
+    pub fn apply_add_liquidity(
+        &mut self,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        let base = add(self.base, base_amount)?;
+        let quote = add(self.quote, quote_amount)?;
+
+        self.base = base;
+        self.quote = quote;
 
-        self.base += base_amount;
-        self.quote -= quote_amount;
+        Ok(())
     }
 
-    pub fn apply_sell_base_for_quote(&mut self, quote_amount: u128) {
-        self.apply_sell_base(self.calculate_base_for_quote_amount(quote_amount))
+    pub fn apply_remove_liquidity(
+        &mut self,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        let base = sub(self.base, base_amount)?;
+        let quote = sub(self.quote, quote_amount)?;
+
+        self.base = base;
+        self.quote = quote;
+
+        Ok(())
     }
 
     pub fn has_enough_quote(&self, quote: u128) -> bool {
@@ -50,8 +139,526 @@ impl Balance {
     }
 }
 
+/// Pricing formula of a pool, quoting trades against its `Balance`
+pub trait Curve {
+    /// Base returned for spending `quote_amount`, rounded down
+    fn calculate_base_for_quote_amount(
+        &self,
+        balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError>;
+
+    /// Quote returned for selling `base_amount`, rounded down
+    fn calculate_quote_for_base_amount(
+        &self,
+        balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError>;
+
+    /// Quote required to take exactly `base_amount` out of the pool, rounded up
+    fn calculate_quote_for_base_out(
+        &self,
+        balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError>;
+
+    /// Base required to take exactly `quote_amount` out of the pool, rounded up
+    fn calculate_base_for_quote_out(
+        &self,
+        balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError>;
+
+    /// Marginal price in quote units per whole token, rounded down
+    fn spot_price(&self, balance: &Balance) -> Result<u128, IWRError>;
+
+    fn apply_buy(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        balance.apply_buy(base_amount, quote_amount)
+    }
+
+    fn apply_sell(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        balance.apply_sell(base_amount, quote_amount)
+    }
+}
+
+/// Curve selected at initialization
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
+pub enum CurveType {
+    /// `base * quote = k` over the pool balance
+    ConstantProduct,
+    Linear(LinearCurve),
+    Exponential(ExponentialCurve),
+    ConstantSum(ConstantSumCurve),
+}
+
+impl CurveType {
+    pub fn is_valid(&self) -> bool {
+        match self {
+            CurveType::ConstantProduct => true,
+            CurveType::Linear(c) => c.initial_price > 0 || c.slope > 0,
+            CurveType::Exponential(c) => c.initial_price > 0 && c.growth_rate > 0,
+            CurveType::ConstantSum(c) => c.price > 0,
+        }
+    }
+
+    fn as_curve(&self) -> &dyn Curve {
+        match self {
+            CurveType::ConstantProduct => &ConstantProductCurve,
+            CurveType::Linear(c) => c,
+            CurveType::Exponential(c) => c,
+            CurveType::ConstantSum(c) => c,
+        }
+    }
+}
+
+impl Curve for CurveType {
+    fn calculate_base_for_quote_amount(
+        &self,
+        balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        self.as_curve()
+            .calculate_base_for_quote_amount(balance, quote_amount)
+    }
+
+    fn calculate_quote_for_base_amount(
+        &self,
+        balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        self.as_curve()
+            .calculate_quote_for_base_amount(balance, base_amount)
+    }
+
+    fn calculate_quote_for_base_out(
+        &self,
+        balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        self.as_curve()
+            .calculate_quote_for_base_out(balance, base_amount)
+    }
+
+    fn calculate_base_for_quote_out(
+        &self,
+        balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        self.as_curve()
+            .calculate_base_for_quote_out(balance, quote_amount)
+    }
+
+    fn spot_price(&self, balance: &Balance) -> Result<u128, IWRError> {
+        self.as_curve().spot_price(balance)
+    }
+
+    fn apply_buy(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        match self {
+            CurveType::ConstantProduct => {
+                ConstantProductCurve.apply_buy(balance, base_amount, quote_amount)
+            }
+            CurveType::Linear(c) => c.apply_buy(balance, base_amount, quote_amount),
+            CurveType::Exponential(c) => c.apply_buy(balance, base_amount, quote_amount),
+            CurveType::ConstantSum(c) => c.apply_buy(balance, base_amount, quote_amount),
+        }
+    }
+
+    fn apply_sell(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        match self {
+            CurveType::ConstantProduct => {
+                ConstantProductCurve.apply_sell(balance, base_amount, quote_amount)
+            }
+            CurveType::Linear(c) => c.apply_sell(balance, base_amount, quote_amount),
+            CurveType::Exponential(c) => c.apply_sell(balance, base_amount, quote_amount),
+            CurveType::ConstantSum(c) => c.apply_sell(balance, base_amount, quote_amount),
+        }
+    }
+}
+
+struct ConstantProductCurve;
+
+impl Curve for ConstantProductCurve {
+    fn calculate_base_for_quote_amount(
+        &self,
+        balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        balance.calculate_base_for_quote_amount(quote_amount)
+    }
+
+    fn calculate_quote_for_base_amount(
+        &self,
+        balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        balance.calculate_quote_for_base_amount(base_amount)
+    }
+
+    fn calculate_quote_for_base_out(
+        &self,
+        balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        balance.calculate_quote_for_base_out(base_amount)
+    }
+
+    fn calculate_base_for_quote_out(
+        &self,
+        balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        balance.calculate_base_for_quote_out(quote_amount)
+    }
+
+    fn spot_price(&self, balance: &Balance) -> Result<u128, IWRError> {
+        mul_div_floor(balance.quote, BASE_UNIT, balance.base)
+    }
+}
+
+// price curves move in steps of a billionth of a token, keeping squares within u128
+const CURVE_STEP: u128 = 1000000000;
+const STEPS_PER_TOKEN: u128 = BASE_UNIT / CURVE_STEP;
+
+fn to_steps_floor(base: u128) -> u128 {
+    base / CURVE_STEP
+}
+
+fn to_steps_ceil(base: u128) -> Result<u128, IWRError> {
+    div_ceil(base, CURVE_STEP)
+}
+
+/// Price growing by `slope` per whole token sold, starting at `initial_price`;
+/// prices are in quote units per whole token
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
+pub struct LinearCurve {
+    pub initial_price: u128,
+    pub slope: u128,
+    /// Base sold along the curve so far
+    pub sold: u128,
+}
+
+impl LinearCurve {
+    // twice the quote paid for the first `steps`, scaled by STEPS_PER_TOKEN^2
+    fn cumulative(&self, steps: u128) -> Result<u128, IWRError> {
+        add(
+            mul(mul(2 * STEPS_PER_TOKEN, self.initial_price)?, steps)?,
+            mul(mul(self.slope, steps)?, steps)?,
+        )
+    }
+
+    fn denominator() -> u128 {
+        2 * STEPS_PER_TOKEN * STEPS_PER_TOKEN
+    }
+
+    /// Largest step count whose cumulative value doesn't exceed `cumulative`
+    fn steps_for_cumulative(&self, cumulative: u128) -> Result<u128, IWRError> {
+        let price_term = mul(self.initial_price, STEPS_PER_TOKEN)?;
+
+        if self.slope == 0 {
+            return div(cumulative, mul(2, price_term)?);
+        }
+
+        let discriminant = add(mul(price_term, price_term)?, mul(self.slope, cumulative)?)?;
+
+        div(sub(isqrt(discriminant), price_term)?, self.slope)
+    }
+}
+
+impl Curve for LinearCurve {
+    fn calculate_base_for_quote_amount(
+        &self,
+        _balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let start = to_steps_ceil(self.sold)?;
+        let end = self.steps_for_cumulative(add(
+            self.cumulative(start)?,
+            mul(quote_amount, LinearCurve::denominator())?,
+        )?)?;
+
+        Ok(mul(end, CURVE_STEP)?.saturating_sub(self.sold))
+    }
+
+    fn calculate_quote_for_base_amount(
+        &self,
+        _balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let end = to_steps_floor(self.sold);
+        let start = to_steps_ceil(self.sold.saturating_sub(base_amount))?.min(end);
+
+        div(
+            sub(self.cumulative(end)?, self.cumulative(start)?)?,
+            LinearCurve::denominator(),
+        )
+    }
+
+    fn calculate_quote_for_base_out(
+        &self,
+        _balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let start = to_steps_floor(self.sold);
+        let end = to_steps_ceil(add(self.sold, base_amount)?)?;
+
+        div_ceil(
+            sub(self.cumulative(end)?, self.cumulative(start)?)?,
+            LinearCurve::denominator(),
+        )
+    }
+
+    fn calculate_base_for_quote_out(
+        &self,
+        _balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let end = to_steps_floor(self.sold);
+        let cumulative = self.cumulative(end)?;
+        let required = mul(quote_amount, LinearCurve::denominator())?;
+
+        // more than the curve ever took in, no amount of circulating base covers it
+        if required > cumulative {
+            return add(self.sold, CURVE_STEP);
+        }
+
+        let start = self.steps_for_cumulative(cumulative - required)?;
+
+        sub(self.sold, mul(start, CURVE_STEP)?)
+    }
+
+    fn spot_price(&self, _balance: &Balance) -> Result<u128, IWRError> {
+        add(
+            self.initial_price,
+            mul_div_floor(self.slope, self.sold, BASE_UNIT)?,
+        )
+    }
+
+    fn apply_buy(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        let sold = add(self.sold, base_amount)?;
+        balance.apply_buy(base_amount, quote_amount)?;
+        self.sold = sold;
+
+        Ok(())
+    }
+
+    fn apply_sell(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        let sold = sub(self.sold, base_amount)?;
+        balance.apply_sell(base_amount, quote_amount)?;
+        self.sold = sold;
+
+        Ok(())
+    }
+}
+
+/// Price growing continuously by `growth_rate` (fixed-point, 1e18 = 100%) per whole token
+/// sold, starting at `initial_price` quote units per whole token
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
+pub struct ExponentialCurve {
+    pub initial_price: u128,
+    pub growth_rate: u128,
+    /// Base sold along the curve so far
+    pub sold: u128,
+}
+
+impl ExponentialCurve {
+    /// e^(growth_rate * tokens), in WAD
+    fn growth(&self, steps: u128) -> Result<u128, IWRError> {
+        exp_wad(mul_div_floor(self.growth_rate, steps, STEPS_PER_TOKEN)?)
+    }
+
+    /// Largest step count whose growth doesn't exceed `growth`, with some slack
+    /// covering the approximation error of `exp_wad` and `ln_wad`
+    fn steps_for_growth(&self, growth: u128) -> Result<u128, IWRError> {
+        mul_div_floor(
+            ln_wad(growth)?.saturating_sub(LN_SLACK),
+            STEPS_PER_TOKEN,
+            self.growth_rate,
+        )
+    }
+}
+
+impl Curve for ExponentialCurve {
+    fn calculate_base_for_quote_amount(
+        &self,
+        _balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let start = to_steps_ceil(self.sold)?;
+        let end = self.steps_for_growth(add(
+            self.growth(start)?,
+            mul_div_floor(quote_amount, self.growth_rate, self.initial_price)?,
+        )?)?;
+
+        Ok(mul(end, CURVE_STEP)?.saturating_sub(self.sold))
+    }
+
+    fn calculate_quote_for_base_amount(
+        &self,
+        _balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let end = to_steps_floor(self.sold);
+        let start = to_steps_ceil(self.sold.saturating_sub(base_amount))?.min(end);
+
+        mul_div_floor(
+            self.initial_price,
+            sub(self.growth(end)?, self.growth(start)?)?,
+            self.growth_rate,
+        )
+    }
+
+    fn calculate_quote_for_base_out(
+        &self,
+        _balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let start = to_steps_floor(self.sold);
+        let end = to_steps_ceil(add(self.sold, base_amount)?)?;
+
+        mul_div_ceil(
+            self.initial_price,
+            sub(self.growth(end)?, self.growth(start)?)?,
+            self.growth_rate,
+        )
+    }
+
+    fn calculate_base_for_quote_out(
+        &self,
+        _balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        let end = to_steps_floor(self.sold);
+        let growth = self.growth(end)?;
+        let required = mul_div_ceil(quote_amount, self.growth_rate, self.initial_price)?;
+
+        // more than the curve ever took in, no amount of circulating base covers it
+        if required > growth - WAD {
+            return add(self.sold, CURVE_STEP);
+        }
+
+        let start = self.steps_for_growth(growth - required)?;
+
+        sub(self.sold, mul(start, CURVE_STEP)?)
+    }
+
+    fn spot_price(&self, _balance: &Balance) -> Result<u128, IWRError> {
+        mul_div_floor(
+            self.initial_price,
+            self.growth(to_steps_floor(self.sold))?,
+            WAD,
+        )
+    }
+
+    fn apply_buy(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        let sold = add(self.sold, base_amount)?;
+        balance.apply_buy(base_amount, quote_amount)?;
+        self.sold = sold;
+
+        Ok(())
+    }
+
+    fn apply_sell(
+        &mut self,
+        balance: &mut Balance,
+        base_amount: u128,
+        quote_amount: u128,
+    ) -> Result<(), IWRError> {
+        let sold = sub(self.sold, base_amount)?;
+        balance.apply_sell(base_amount, quote_amount)?;
+        self.sold = sold;
+
+        Ok(())
+    }
+}
+
+/// Fixed `price` in quote units per whole token, in both directions
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
+pub struct ConstantSumCurve {
+    pub price: u128,
+}
+
+impl Curve for ConstantSumCurve {
+    fn calculate_base_for_quote_amount(
+        &self,
+        _balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        mul_div_floor(quote_amount, BASE_UNIT, self.price)
+    }
+
+    fn calculate_quote_for_base_amount(
+        &self,
+        _balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        mul_div_floor(base_amount, self.price, BASE_UNIT)
+    }
+
+    fn calculate_quote_for_base_out(
+        &self,
+        _balance: &Balance,
+        base_amount: u128,
+    ) -> Result<u128, IWRError> {
+        mul_div_ceil(base_amount, self.price, BASE_UNIT)
+    }
+
+    fn calculate_base_for_quote_out(
+        &self,
+        _balance: &Balance,
+        quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        mul_div_ceil(quote_amount, BASE_UNIT, self.price)
+    }
+
+    fn spot_price(&self, _balance: &Balance) -> Result<u128, IWRError> {
+        Ok(self.price)
+    }
+}
+
+// well above the few units of error the `exp_wad` and `ln_wad` series accumulate
+const LN_SLACK: u128 = 1000;
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::math::U256;
+    use crate::state::Fees;
+    use proptest::prelude::*;
 
     #[test]
     fn test_calculations() {
@@ -62,16 +669,21 @@ mod tests {
 
         let one_whole_quote = 1000000000u128;
         let one_whole_base = 1000000000000000000u128;
+        let fees = Fees {
+            buy_fee_bps: 30,
+            sell_fee_bps: 30,
+            lp_fee_share_bps: 0,
+        };
 
         /*
         let to_buy = one_whole_quote / 7;
 
-        let purchased_base = balance.calculate_base_for_quote_amount(to_buy);
+        let purchased_base = balance.calculate_base_for_quote_amount(to_buy).unwrap();
         balance.process_purchase_for_quote_amount(to_buy);
         println!("{:#?}", balance);
         println!("purchased_base = {:#?}", purchased_base);
 
-        let purchased_quote = balance.calculate_quote_for_base_amount(purchased_base);
+        let purchased_quote = balance.calculate_quote_for_base_amount(purchased_base).unwrap();
         balance.process_purchase_for_base_amount(purchased_base);
         println!("{:#?}", balance);
         println!("purchased_quote = {:#?}", purchased_quote);
@@ -91,7 +703,9 @@ mod tests {
 
         let mut purchases: Vec<u128> = vec![];
         for _ in 0..25 {
-            let purchased_base = balance.calculate_base_for_quote_amount(one_whole_quote);
+            let purchased_base = balance
+                .calculate_base_for_quote_amount(one_whole_quote)
+                .unwrap();
             purchases.push(purchased_base);
 
             println!(
@@ -102,10 +716,10 @@ mod tests {
                 balance.quote / one_whole_quote,
                 purchased_base,
                 one_whole_quote,
-                balance.calculate_fee_of(one_whole_quote)
+                fees.calculate_buy_fee(one_whole_quote).unwrap()
             );
 
-            balance.apply_buy_base_for_quote(one_whole_quote);
+            balance.apply_buy_base_for_quote(one_whole_quote).unwrap();
         }
 
         println!(
@@ -128,10 +742,10 @@ mod tests {
                 balance.quote,
                 balance.quote / one_whole_quote,
                 *p,
-                balance.calculate_quote_for_base_amount(*p),
+                balance.calculate_quote_for_base_amount(*p).unwrap(),
             );
 
-            balance.apply_sell_base(*p);
+            balance.apply_sell_base(*p).unwrap();
         }
 
         println!(
@@ -142,4 +756,361 @@ mod tests {
             balance.quote / one_whole_quote,
         );
     }
+
+    #[test]
+    fn test_exact_out_calculations() {
+        let balance = Balance {
+            base: 1000000_000000000000000000u128,
+            quote: 1_000000000u128,
+        };
+
+        let one_whole_base = 1000000000000000000u128;
+
+        // paying the rounded-up quote always yields at least the requested base
+        for base_out in [1u128, 7, 1000000000000000000, 333333_333333333333333333] {
+            let quote_in = balance.calculate_quote_for_base_out(base_out).unwrap();
+            assert!(balance.calculate_base_for_quote_amount(quote_in).unwrap() >= base_out);
+            assert!(
+                balance
+                    .calculate_base_for_quote_amount(quote_in - 1)
+                    .unwrap()
+                    < base_out
+            );
+        }
+
+        // burning the rounded-up base always yields at least the requested quote
+        for quote_out in [1u128, 7, 1000000, 500000000] {
+            let base_in = balance.calculate_base_for_quote_out(quote_out).unwrap();
+            assert!(balance.calculate_quote_for_base_amount(base_in).unwrap() >= quote_out);
+            assert!(
+                balance
+                    .calculate_quote_for_base_amount(base_in - 1)
+                    .unwrap()
+                    < quote_out
+            );
+        }
+
+        let mut bought = balance.clone();
+        bought.apply_buy_base(one_whole_base).unwrap();
+        assert_eq!(bought.base, balance.base - one_whole_base);
+        assert_eq!(
+            bought.quote,
+            balance.quote
+                + balance
+                    .calculate_quote_for_base_out(one_whole_base)
+                    .unwrap()
+        );
+
+        let mut sold = balance.clone();
+        sold.apply_sell_base_for_quote(1000000).unwrap();
+        assert_eq!(sold.quote, balance.quote - 1000000);
+        assert_eq!(
+            sold.base,
+            balance.base + balance.calculate_base_for_quote_out(1000000).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_liquidity_calculations() {
+        let mut balance = Balance {
+            base: 1000000_000000000000000000u128,
+            quote: 1_000000000u128,
+        };
+
+        let total_shares = 1_000000000u128;
+
+        // depositing at the current ratio keeps the price intact
+        let quote_in = 250000000u128;
+        let base_in = balance.calculate_base_for_liquidity(quote_in).unwrap();
+        let shares = balance
+            .calculate_shares_for_liquidity(quote_in, total_shares)
+            .unwrap();
+        assert_eq!(base_in, 250000_000000000000000000);
+        assert_eq!(shares, 250000000);
+
+        balance.apply_add_liquidity(base_in, quote_in).unwrap();
+        assert_eq!(balance.base * 4, 5000000_000000000000000000);
+        assert_eq!(balance.quote * 4, 5_000000000);
+
+        // withdrawing the same shares returns what was deposited
+        let (base_out, quote_out) = balance
+            .calculate_liquidity_for_shares(shares, total_shares + shares)
+            .unwrap();
+        assert_eq!((base_out, quote_out), (base_in, quote_in));
+
+        balance.apply_remove_liquidity(base_out, quote_out).unwrap();
+        assert_eq!(balance.base, 1000000_000000000000000000);
+        assert_eq!(balance.quote, 1_000000000);
+
+        // rounding never favors the depositor
+        let mut skewed = Balance {
+            base: 999999_999999999999999999u128,
+            quote: 1_000000007u128,
+        };
+        let base_in = skewed.calculate_base_for_liquidity(3).unwrap();
+        let shares = skewed
+            .calculate_shares_for_liquidity(3, total_shares)
+            .unwrap();
+        skewed.apply_add_liquidity(base_in, 3).unwrap();
+
+        let (base_out, quote_out) = skewed
+            .calculate_liquidity_for_shares(shares, total_shares + shares)
+            .unwrap();
+        assert!(base_out <= base_in);
+        assert!(quote_out <= 3);
+    }
+
+    #[test]
+    fn test_curves() {
+        let one_whole_base = 1000000000000000000u128;
+
+        let curves = [
+            CurveType::ConstantProduct,
+            CurveType::Linear(LinearCurve {
+                initial_price: 1000,
+                slope: 10,
+                sold: 0,
+            }),
+            CurveType::Linear(LinearCurve {
+                initial_price: 0,
+                slope: 1,
+                sold: 0,
+            }),
+            CurveType::Exponential(ExponentialCurve {
+                initial_price: 1000,
+                growth_rate: 10000000000000,
+                sold: 0,
+            }),
+            CurveType::ConstantSum(ConstantSumCurve { price: 1000 }),
+        ];
+
+        for mut curve in curves {
+            let mut balance = Balance {
+                base: 1000000_000000000000000000u128,
+                quote: 1_000000000u128,
+            };
+
+            assert!(curve.is_valid());
+
+            let mut last_price = 0;
+            let mut last_spot_price = 0;
+            for quote_in in [1000u128, 1000000, 100000000] {
+                let base_out = curve
+                    .calculate_base_for_quote_amount(&balance, quote_in)
+                    .unwrap();
+                assert!(base_out > 0);
+
+                // prices never fall while buying
+                let price = curve
+                    .calculate_quote_for_base_out(&balance, one_whole_base)
+                    .unwrap();
+                assert!(price >= last_price);
+                last_price = price;
+
+                // the next whole token never costs less than the marginal price
+                let spot_price = curve.spot_price(&balance).unwrap();
+                assert!(spot_price >= last_spot_price);
+                assert!(price >= spot_price);
+                last_spot_price = spot_price;
+
+                // a round trip never returns more than was paid
+                curve.apply_buy(&mut balance, base_out, quote_in).unwrap();
+                assert!(
+                    curve
+                        .calculate_quote_for_base_amount(&balance, base_out)
+                        .unwrap()
+                        <= quote_in
+                );
+            }
+
+            // exact-out quotes cover at least the requested amounts
+            let quote_in = curve
+                .calculate_quote_for_base_out(&balance, one_whole_base)
+                .unwrap();
+            let mut bought = balance.clone();
+            let mut bought_curve = curve.clone();
+            bought_curve
+                .apply_buy(&mut bought, one_whole_base, quote_in)
+                .unwrap();
+            assert!(
+                bought_curve
+                    .calculate_quote_for_base_amount(&bought, one_whole_base)
+                    .unwrap()
+                    <= quote_in
+            );
+
+            let base_in = curve.calculate_base_for_quote_out(&balance, 1000).unwrap();
+            assert!(
+                curve
+                    .calculate_quote_for_base_amount(&balance, base_in)
+                    .unwrap()
+                    >= 1000
+            );
+
+            // selling everything back leaves the quote that came in
+            let quote_before = balance.quote;
+            let sold = 1000000_000000000000000000u128 - balance.base;
+            let quote_back = curve
+                .calculate_quote_for_base_amount(&balance, sold)
+                .unwrap();
+            curve.apply_sell(&mut balance, sold, quote_back).unwrap();
+            assert!(balance.quote <= quote_before);
+            assert_eq!(balance.base, 1000000_000000000000000000u128);
+        }
+
+        assert!(!CurveType::Linear(LinearCurve::default()).is_valid());
+        assert!(!CurveType::Exponential(ExponentialCurve {
+            initial_price: 1000,
+            ..ExponentialCurve::default()
+        })
+        .is_valid());
+        assert!(!CurveType::ConstantSum(ConstantSumCurve::default()).is_valid());
+    }
+
+    #[test]
+    fn test_overflow() {
+        let overflow = Err(IWRError::Overflow);
+
+        // intermediate products are widened, only results beyond u128 overflow
+        let balance = Balance {
+            base: u128::MAX,
+            quote: 2,
+        };
+        assert_eq!(
+            balance.calculate_base_for_quote_amount(1),
+            Ok(u128::MAX / 3)
+        );
+        assert_eq!(balance.calculate_base_for_quote_out(1), Ok(u128::MAX));
+        assert_eq!(balance.calculate_base_for_liquidity(3), overflow);
+        assert_eq!(
+            balance.calculate_shares_for_liquidity(u128::MAX, 3),
+            overflow
+        );
+        assert_eq!(
+            balance.calculate_liquidity_for_shares(2, 1),
+            Err(IWRError::Overflow)
+        );
+
+        // sums beyond u128
+        let balance = Balance {
+            base: 1,
+            quote: u128::MAX,
+        };
+        assert_eq!(balance.calculate_base_for_quote_amount(1), overflow);
+
+        // taking out all of a reserve, or more
+        let balance = Balance {
+            base: 10,
+            quote: 10,
+        };
+        assert_eq!(balance.calculate_quote_for_base_out(10), overflow);
+        assert_eq!(balance.calculate_quote_for_base_out(11), overflow);
+        assert_eq!(balance.calculate_base_for_quote_out(10), overflow);
+        assert_eq!(
+            balance.calculate_liquidity_for_shares(1, 0),
+            Err(IWRError::Overflow)
+        );
+
+        // failed updates leave the balance untouched
+        let mut balance = Balance {
+            base: 10,
+            quote: u128::MAX,
+        };
+        assert_eq!(balance.apply_buy(11, 0), Err(IWRError::Overflow));
+        assert_eq!(balance.apply_buy(1, 1), Err(IWRError::Overflow));
+        assert_eq!(balance.apply_sell(u128::MAX, 0), Err(IWRError::Overflow));
+        assert_eq!(balance.apply_sell(0, u128::MAX), Ok(()));
+        assert_eq!(balance.apply_sell(0, 1), Err(IWRError::Overflow));
+        assert_eq!(
+            balance.apply_add_liquidity(u128::MAX, 0),
+            Err(IWRError::Overflow)
+        );
+        assert_eq!(
+            balance.apply_remove_liquidity(11, 0),
+            Err(IWRError::Overflow)
+        );
+        assert_eq!(balance, Balance { base: 10, quote: 0 });
+
+        // curves
+        let curve = CurveType::ConstantSum(ConstantSumCurve { price: u128::MAX });
+        assert_eq!(
+            curve.calculate_quote_for_base_amount(&balance, 2 * BASE_UNIT),
+            overflow
+        );
+
+        let curve = CurveType::ConstantSum(ConstantSumCurve { price: 1 });
+        assert_eq!(
+            curve.calculate_base_for_quote_amount(&balance, u128::MAX),
+            overflow
+        );
+
+        let mut curve = CurveType::Linear(LinearCurve {
+            initial_price: 1000,
+            slope: 10,
+            sold: u128::MAX,
+        });
+        assert_eq!(curve.calculate_quote_for_base_out(&balance, 1), overflow);
+        assert_eq!(
+            curve.calculate_base_for_quote_amount(&balance, u128::MAX),
+            overflow
+        );
+        assert_eq!(curve.apply_buy(&mut balance, 1, 0), Err(IWRError::Overflow));
+
+        let curve = CurveType::Exponential(ExponentialCurve {
+            initial_price: 1000,
+            growth_rate: WAD,
+            sold: 0,
+        });
+        assert_eq!(
+            curve.calculate_quote_for_base_out(&balance, 200 * BASE_UNIT),
+            overflow
+        );
+        assert_eq!(
+            curve.calculate_base_for_quote_amount(&balance, u128::MAX),
+            overflow
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trips_never_extract_value(
+            base in 1u128..1000000000_000000000000000000,
+            quote in 1u128..1000000000000000000,
+            amount in 1u128..1000000000000000000,
+        ) {
+            let initial = Balance { base, quote };
+            let invariant = U256::from(base) * U256::from(quote);
+
+            // buying for `amount` and selling everything bought straight back
+            let mut balance = initial.clone();
+            let base_out = balance.calculate_base_for_quote_amount(amount).unwrap();
+            balance.apply_buy(base_out, amount).unwrap();
+            prop_assert!(U256::from(balance.base) * U256::from(balance.quote) >= invariant);
+
+            let quote_back = balance.calculate_quote_for_base_amount(base_out).unwrap();
+            balance.apply_sell(base_out, quote_back).unwrap();
+            prop_assert!(quote_back <= amount);
+            prop_assert_eq!(balance.base, base);
+            prop_assert!(balance.quote >= quote);
+
+            // buying exactly `amount` base and selling it straight back
+            prop_assume!(amount < base);
+            let mut balance = initial.clone();
+            let quote_in = balance.calculate_quote_for_base_out(amount).unwrap();
+            balance.apply_buy(amount, quote_in).unwrap();
+            prop_assert!(U256::from(balance.base) * U256::from(balance.quote) >= invariant);
+
+            let quote_back = balance.calculate_quote_for_base_amount(amount).unwrap();
+            prop_assert!(quote_back <= quote_in);
+
+            // selling `amount` base and buying it straight back
+            let mut balance = initial;
+            let quote_out = balance.calculate_quote_for_base_amount(amount).unwrap();
+            balance.apply_sell(amount, quote_out).unwrap();
+            prop_assert!(U256::from(balance.base) * U256::from(balance.quote) >= invariant);
+
+            let base_back = balance.calculate_base_for_quote_amount(quote_out).unwrap();
+            prop_assert!(base_back <= amount);
+        }
+    }
 }
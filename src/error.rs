@@ -18,6 +18,114 @@ pub enum IWRError {
     /// Calculation overflow
     #[error("Calculation overflow")]
     Overflow,
+
+    /// Swap return fell below the caller's minimum
+    #[error("slippage tolerance exceeded")]
+    SlippageExceeded,
+
+    /// Fee rate above the protocol maximum
+    #[error("fee exceeds the protocol maximum")]
+    FeeTooHigh,
+
+    /// Fee account differs from the beneficiary recorded in the pool state
+    #[error("incorrect beneficiary")]
+    IncorrectBeneficiary,
+
+    /// All trading is suspended by the authority
+    #[error("trading is paused")]
+    TradingPaused,
+
+    /// Buying is suspended by the authority
+    #[error("buying is halted")]
+    BuysHalted,
+
+    /// Selling is suspended by the authority
+    #[error("selling is halted")]
+    SellsHalted,
+
+    /// Curve parameters that can't price any trade
+    #[error("invalid curve parameters")]
+    InvalidCurve,
+
+    /// Operation not available on the pool's curve
+    #[error("unsupported by the pool's curve")]
+    UnsupportedCurve,
+
+    /// Virtual reserves leave the pool without a starting price, or don't fit its curve
+    #[error("invalid virtual reserves")]
+    InvalidVirtualReserves,
+
+    /// Graduation requested before the quote balance reached the threshold
+    #[error("graduation threshold not reached")]
+    GraduationThresholdNotReached,
+
+    /// The pool has graduated and no longer trades
+    #[error("pool has graduated")]
+    PoolGraduated,
+
+    /// Mint decimals finer than the pool's internal base precision
+    #[error("invalid mint decimals")]
+    InvalidDecimals,
+
+    /// Account isn't owned by the program expected to own it
+    #[error("incorrect account owner")]
+    IncorrectOwner,
+
+    /// Account that has to sign the instruction didn't
+    #[error("missing required signature")]
+    MissingSignature,
+
+    /// Account modified by the instruction was passed read-only
+    #[error("account is not writable")]
+    AccountNotWritable,
+
+    /// Program account other than the one the instruction invokes
+    #[error("incorrect program")]
+    IncorrectProgram,
+
+    /// Account other than the one derived or recorded for the pool
+    #[error("incorrect account address")]
+    IncorrectAddress,
+
+    /// `Initialize` called for a pool that already exists
+    #[error("pool is already initialized")]
+    AlreadyInitialized,
+
+    /// State written with a layout other than the current one
+    #[error("unsupported state version")]
+    UnsupportedStateVersion,
+
+    /// `MigrateState` called for a state already in the current layout
+    #[error("state is already migrated")]
+    AlreadyMigrated,
+
+    /// `ClosePool` called while the pool still trades
+    #[error("pool is not halted")]
+    PoolNotHalted,
+
+    /// Liquidity providers other than the seed still hold LP shares
+    #[error("liquidity is outstanding")]
+    LiquidityOutstanding,
+
+    /// The quote balance reached the graduation threshold, trading waits for `Graduate`
+    #[error("graduation threshold reached")]
+    GraduationThresholdReached,
+
+    /// `Initialize` called with the index kept for pools of the first release
+    #[error("pool index is reserved")]
+    ReservedPoolIndex,
+
+    /// `AddLiquidity` called on a pool with a graduation threshold
+    #[error("pool graduates")]
+    GraduatingPool,
+
+    /// `ClosePool` called on a pool that hasn't graduated while its tokens are held outside
+    #[error("tokens circulating")]
+    TokensCirculating,
+
+    /// Trade that comes to less than one whole token
+    #[error("amount too small")]
+    AmountTooSmall,
 }
 
 impl From<IWRError> for ProgramError {
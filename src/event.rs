@@ -0,0 +1,262 @@
+// Structured events, logged as base64 program data so indexers don't have to parse `msg!`
+use crate::balance::{Balance, CurveType};
+use crate::state::PoolStatus;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::log::sol_log_data;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use std::io::{Error, ErrorKind};
+
+const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+/// Logged by every instruction that changes a pool, base amounts in `BASE_UNIT` precision
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub enum PoolEvent {
+    Initialized {
+        state: Pubkey,
+        creator: Pubkey,
+        pool_index: u64,
+        authority: Pubkey,
+        quote_mint: Pubkey,
+        curve: CurveType,
+        /// Real reserves the pool starts with
+        balance: Balance,
+    },
+    Bought {
+        state: Pubkey,
+        customer: Pubkey,
+        /// Quote spent on the curve, the fee is charged on top
+        quote_amount: u128,
+        /// Base transferred to the customer, in whole tokens
+        base_amount: u128,
+        fee: u128,
+        /// Real reserves after the trade
+        balance: Balance,
+    },
+    Sold {
+        state: Pubkey,
+        customer: Pubkey,
+        base_amount: u128,
+        /// Quote paid out to the customer, net of the fee
+        quote_amount: u128,
+        fee: u128,
+        /// Real reserves after the trade
+        balance: Balance,
+    },
+    /// Swap fee split between the beneficiary and the liquidity providers
+    FeeCollected {
+        state: Pubkey,
+        beneficiary: Pubkey,
+        amount: u128,
+        /// Part of the fee left in the pool on top of `amount`
+        lp_amount: u128,
+    },
+    AdminChanged {
+        state: Pubkey,
+        /// Signer of the change, the new authority when accepting
+        authority: Pubkey,
+        change: AdminChange,
+    },
+    LiquidityAdded {
+        state: Pubkey,
+        provider: Pubkey,
+        base_amount: u128,
+        quote_amount: u128,
+        lp_amount: u128,
+    },
+    LiquidityRemoved {
+        state: Pubkey,
+        provider: Pubkey,
+        base_amount: u128,
+        quote_amount: u128,
+        lp_amount: u128,
+    },
+}
+
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub enum AdminChange {
+    Beneficiary(Pubkey),
+    Status(PoolStatus),
+    AuthorityProposed(Pubkey),
+    AuthorityAccepted,
+    /// Raw tokens and quote moved out of the pool
+    Graduated {
+        base_amount: u128,
+        quote_amount: u128,
+    },
+    StateMigrated,
+    /// Raw tokens and quote held beyond the reserves, moved out of the pool
+    Skimmed {
+        base_amount: u128,
+        quote_amount: u128,
+    },
+    /// Raw tokens burnt and quote moved out as the pool's accounts were closed
+    Closed {
+        base_amount: u128,
+        quote_amount: u128,
+    },
+    /// Ring buffer of price observations created at the given address, by whoever paid
+    /// for it
+    OracleInitialized(Pubkey),
+}
+
+impl PoolEvent {
+    pub fn emit(&self) -> Result<(), ProgramError> {
+        sol_log_data(&[&self.try_to_vec()?]);
+
+        Ok(())
+    }
+}
+
+/// Decodes the events logged by `program_id` out of a transaction's log messages,
+/// skipping program data logged by any other program along the way and by invocations
+/// that failed, whose changes didn't stick
+pub fn parse_events(program_id: &Pubkey, logs: &[String]) -> Result<Vec<PoolEvent>, Error> {
+    let program_id = program_id.to_string();
+    // the events of each open invocation, handed to its caller once it succeeds
+    let mut invocations: Vec<(&str, Vec<PoolEvent>)> = vec![];
+    let mut events = vec![];
+
+    for log in logs {
+        if let Some(data) = log.strip_prefix(PROGRAM_DATA_PREFIX) {
+            let invocation_events = match invocations.last_mut() {
+                Some((id, invocation_events)) if *id == program_id => invocation_events,
+                _ => continue,
+            };
+
+            for field in data.split(' ') {
+                let bytes =
+                    base64::decode(field).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+                invocation_events.push(PoolEvent::try_from_slice(&bytes)?);
+            }
+        } else if let Some(log) = log.strip_prefix("Program ") {
+            // tracking the invocation stack, logs of nested calls land in between
+            let mut words = log.split(' ');
+
+            match (words.next(), words.next()) {
+                (Some(id), Some("invoke")) => invocations.push((id, vec![])),
+                (Some(_), Some("success")) => {
+                    if let Some((_, invocation_events)) = invocations.pop() {
+                        match invocations.last_mut() {
+                            Some((_, caller_events)) => caller_events.extend(invocation_events),
+                            None => events.extend(invocation_events),
+                        }
+                    }
+                }
+                (Some(_), Some("failed:")) => {
+                    invocations.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // logs cut short leave invocations open, their outcome unknown
+    for (_, invocation_events) in invocations {
+        events.extend(invocation_events);
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program_data(event: &PoolEvent) -> String {
+        format!(
+            "{}{}",
+            PROGRAM_DATA_PREFIX,
+            base64::encode(event.try_to_vec().unwrap())
+        )
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let program_id = crate::id();
+        let other_program_id = spl_token::id();
+        let state = Pubkey::new_unique();
+
+        let bought = PoolEvent::Bought {
+            state,
+            customer: Pubkey::new_unique(),
+            quote_amount: 1000000,
+            base_amount: 999000000000000000,
+            fee: 3000,
+            balance: Balance {
+                base: 1000,
+                quote: 2000,
+            },
+        };
+        let fee_collected = PoolEvent::FeeCollected {
+            state,
+            beneficiary: Pubkey::new_unique(),
+            amount: 3000,
+            lp_amount: 0,
+        };
+        let paused = PoolEvent::AdminChanged {
+            state,
+            authority: Pubkey::new_unique(),
+            change: AdminChange::Status(PoolStatus::Paused),
+        };
+
+        let logs = [
+            format!("Program {} invoke [1]", program_id),
+            "Program log: exchanging 1000000 lamports for 999000000000000000 base".to_string(),
+            format!("Program {} invoke [2]", other_program_id),
+            // data of nested calls doesn't belong to the pool
+            program_data(&paused),
+            format!("Program {} success", other_program_id),
+            program_data(&bought),
+            program_data(&fee_collected),
+            format!(
+                "Program {} consumed 5000 of 200000 compute units",
+                program_id
+            ),
+            format!("Program {} success", program_id),
+            format!("Program {} invoke [1]", program_id),
+            program_data(&paused),
+            format!("Program {} failed: custom program error: 0x7", program_id),
+            format!("Program {} invoke [1]", other_program_id),
+            program_data(&bought),
+            format!("Program {} success", other_program_id),
+        ];
+
+        // nor does data of the failed invocation
+        assert_eq!(
+            parse_events(&program_id, &logs).unwrap(),
+            vec![bought.clone(), fee_collected.clone()]
+        );
+
+        // the pool's data travels up through its callers, and is kept when the log is cut
+        // short
+        let logs = [
+            format!("Program {} invoke [1]", other_program_id),
+            format!("Program {} invoke [2]", program_id),
+            program_data(&bought),
+            format!("Program {} success", program_id),
+            format!("Program {} success", other_program_id),
+            format!("Program {} invoke [1]", program_id),
+            program_data(&fee_collected),
+            "Log truncated".to_string(),
+        ];
+        assert_eq!(
+            parse_events(&program_id, &logs).unwrap(),
+            vec![bought, fee_collected]
+        );
+        assert_eq!(parse_events(&program_id, &[]).unwrap(), vec![]);
+
+        // data that isn't an event is an error rather than skipped
+        let logs = [
+            format!("Program {} invoke [1]", program_id),
+            format!("{}AQID", PROGRAM_DATA_PREFIX),
+        ];
+        assert!(parse_events(&program_id, &logs).is_err());
+
+        let logs = [
+            format!("Program {} invoke [1]", program_id),
+            format!("{}not base64!", PROGRAM_DATA_PREFIX),
+        ];
+        assert!(parse_events(&program_id, &logs).is_err());
+    }
+}
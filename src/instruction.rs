@@ -1,4 +1,6 @@
-use crate::id;
+use crate::balance::{Balance, CurveType};
+use crate::state::Fees;
+use crate::{get_oracle_address, id};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     instruction::{AccountMeta, Instruction},
@@ -9,37 +11,166 @@ use solana_program::{
 #[derive(Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
 pub enum PoolInstruction {
     Initialize {
+        pool_index: u64,
+        /// Raw tokens minted into the token pool
         total_token_supply: u128,
+        /// Mint decimals, at most `BASE_DECIMALS`
         decimals: u8,
         initial_quote_amount: u128,
+        fees: Fees,
+        curve: CurveType,
+        /// Reserves shaping the constant product price without being deposited
+        virtual_balance: Balance,
+        /// Quote balance at which the pool may graduate, zero to never graduate
+        graduation_threshold: u128,
+        beneficiary: Pubkey,
     },
     Buy(u128),
     Sell(u128),
+    /// Same as `Buy`, but fails if the base return is below `min_base_out`
+    BuyWithSlippage {
+        quote_amount: u128,
+        min_base_out: u128,
+    },
+    /// Same as `Sell`, but fails if the net quote return is below `min_quote_out`
+    SellWithSlippage {
+        base_amount: u128,
+        min_quote_out: u128,
+    },
+    /// Buys exactly `base_out`, failing if it costs more than `max_quote_in` including the fee
+    BuyExactOut {
+        base_out: u128,
+        max_quote_in: u128,
+    },
+    /// Sells for exactly `quote_out` net of the fee, failing if it burns more than `max_base_in`
+    SellExactOut {
+        quote_out: u128,
+        max_base_in: u128,
+    },
+    /// Replaces the account receiving swap fees, signed by the pool authority
+    SetBeneficiary(Pubkey),
+    /// Rejects all swaps until `Resume`
+    Pause,
+    /// Returns the pool to normal trading
+    Resume,
+    /// Rejects buys while still letting customers sell
+    HaltBuysOnly,
+    /// Rejects sells while still letting customers buy
+    HaltSellsOnly,
+    /// Nominates a new authority, which takes over once it sends `AcceptAuthority`
+    ProposeAuthority(Pubkey),
+    /// Completes the transfer, signed by the proposed authority
+    AcceptAuthority,
+    /// Deposits `quote_amount` and the matching base at the current ratio in exchange
+    /// for LP tokens, failing if the base part exceeds `max_base_in`; refused on pools
+    /// with a graduation threshold, which graduate only once no LP tokens are left
+    AddLiquidity {
+        quote_amount: u128,
+        max_base_in: u128,
+    },
+    /// Burns `lp_amount` LP tokens for the proportional share of both reserves
+    RemoveLiquidity {
+        lp_amount: u128,
+        min_base_out: u128,
+        min_quote_out: u128,
+    },
+    /// Ends a pool frozen by its quote balance reaching the graduation threshold, moving the
+    /// remaining reserves to the destinations picked by the authority
+    Graduate,
+    /// Rewrites a state account of an older layout into the current one, growing it as
+    /// needed with the authority paying the extra rent, and the LP mint pools of the
+    /// first release lack
+    MigrateState,
+    /// Previews spending `amount` of quote (buys) or base (sells) without trading, setting
+    /// a `QuoteResult` as return data
+    Quote {
+        side: Side,
+        amount: u128,
+    },
+    /// Creates the pool's ring buffer of price observations, which swaps fill from then on;
+    /// anyone may pay for it
+    InitializeOracle,
+    /// Moves tokens and quote the pools hold beyond the recorded reserves, such as
+    /// donations, to the destinations picked by the authority
+    Skim,
+    /// Winds down a paused or graduated pool: burns the tokens left in the token pool,
+    /// moves the remaining quote out and closes the pool's accounts, their rent going to
+    /// the destination picked by the authority; refused while LP tokens are outstanding and,
+    /// short of graduation, while any of the pool's tokens are held outside the token pool
+    ClosePool,
 }
 
+#[derive(Clone, Copy, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// Return data of `Quote`, amounts as a `Buy` or `Sell` of the same size would settle them
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct QuoteResult {
+    /// Amount going into the curve, buys charged only for the whole tokens they get and
+    /// sells rounded down to whole tokens
+    pub amount_in: u128,
+    /// Base transferred to the buyer, or quote to the seller net of the fee
+    pub amount_out: u128,
+    /// Quote charged on top of a buy or taken out of a sell
+    pub fee: u128,
+    /// Distance of the execution price from the spot price, in basis points
+    pub price_impact_bps: u128,
+    /// Spot prices around the trade, in quote units per whole token
+    pub spot_price_before: u128,
+    pub spot_price_after: u128,
+}
+
+impl QuoteResult {
+    /// Encoded length; return data loses its trailing zeros, so it has to be padded
+    /// back to this before decoding
+    pub const LEN: usize = 16 * 6;
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn initialize_pool(
     authority_address: &Pubkey,
     state_address: &Pubkey,
     mint_address: &Pubkey,
     token_pool_address: &Pubkey,
-    native_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    lp_mint_address: &Pubkey,
+    quote_mint_address: &Pubkey,
+    payer_quote_address: &Pubkey,
+    pool_index: u64,
     total_token_supply: u128,
     decimals: u8,
     initial_quote_amount: u128,
+    fees: Fees,
+    curve: CurveType,
+    virtual_balance: Balance,
+    graduation_threshold: u128,
+    beneficiary_address: &Pubkey,
 ) -> Instruction {
     Instruction::new_with_borsh(
         id(),
         &PoolInstruction::Initialize {
+            pool_index,
             total_token_supply,
             decimals,
             initial_quote_amount,
+            fees,
+            curve,
+            virtual_balance,
+            graduation_threshold,
+            beneficiary: *beneficiary_address,
         },
         vec![
             AccountMeta::new(*authority_address, true),
             AccountMeta::new(*state_address, false),
             AccountMeta::new(*mint_address, false),
             AccountMeta::new(*token_pool_address, false),
-            AccountMeta::new(*native_pool_address, false),
+            AccountMeta::new(*quote_pool_address, false),
+            AccountMeta::new(*lp_mint_address, false),
+            AccountMeta::new_readonly(*quote_mint_address, false),
+            AccountMeta::new(*payer_quote_address, false),
             AccountMeta::new_readonly(system_program::id(), false),
             AccountMeta::new_readonly(spl_token::id(), false),
             AccountMeta::new_readonly(sysvar::rent::id(), false),
@@ -47,62 +178,544 @@ pub fn initialize_pool(
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn buy(
-    authority_address: &Pubkey,
+    creator_address: &Pubkey,
     state_address: &Pubkey,
     mint_address: &Pubkey,
     token_pool_address: &Pubkey,
-    native_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
     beneficiary_address: &Pubkey,
     customer_address: &Pubkey,
     customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
     quote_amount: u128,
 ) -> Instruction {
     Instruction::new_with_borsh(
         id(),
         &PoolInstruction::Buy(quote_amount),
-        vec![
-            AccountMeta::new(*authority_address, true),
-            AccountMeta::new(*state_address, false),
-            AccountMeta::new(*mint_address, false),
-            AccountMeta::new(*token_pool_address, false),
-            AccountMeta::new(*native_pool_address, false),
-            AccountMeta::new(*beneficiary_address, false),
-            AccountMeta::new(*customer_address, true),
-            AccountMeta::new(*customer_token_associated_address, false),
-            AccountMeta::new_readonly(system_program::id(), false),
-            AccountMeta::new_readonly(spl_token::id(), false),
-            AccountMeta::new_readonly(sysvar::rent::id(), false),
-        ],
+        buy_accounts(
+            creator_address,
+            state_address,
+            mint_address,
+            token_pool_address,
+            quote_pool_address,
+            beneficiary_address,
+            customer_address,
+            customer_token_associated_address,
+            customer_quote_address,
+        ),
     )
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn buy_with_slippage(
+    creator_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    customer_address: &Pubkey,
+    customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
+    quote_amount: u128,
+    min_base_out: u128,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::BuyWithSlippage {
+            quote_amount,
+            min_base_out,
+        },
+        buy_accounts(
+            creator_address,
+            state_address,
+            mint_address,
+            token_pool_address,
+            quote_pool_address,
+            beneficiary_address,
+            customer_address,
+            customer_token_associated_address,
+            customer_quote_address,
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn sell(
-    authority_address: &Pubkey,
+    creator_address: &Pubkey,
     state_address: &Pubkey,
     mint_address: &Pubkey,
     token_pool_address: &Pubkey,
-    native_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
     beneficiary_address: &Pubkey,
     customer_address: &Pubkey,
     customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
     base_amount: u128,
 ) -> Instruction {
     Instruction::new_with_borsh(
         id(),
         &PoolInstruction::Sell(base_amount),
+        sell_accounts(
+            creator_address,
+            state_address,
+            mint_address,
+            token_pool_address,
+            quote_pool_address,
+            beneficiary_address,
+            customer_address,
+            customer_token_associated_address,
+            customer_quote_address,
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn sell_with_slippage(
+    creator_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    customer_address: &Pubkey,
+    customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
+    base_amount: u128,
+    min_quote_out: u128,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::SellWithSlippage {
+            base_amount,
+            min_quote_out,
+        },
+        sell_accounts(
+            creator_address,
+            state_address,
+            mint_address,
+            token_pool_address,
+            quote_pool_address,
+            beneficiary_address,
+            customer_address,
+            customer_token_associated_address,
+            customer_quote_address,
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn buy_exact_out(
+    creator_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    customer_address: &Pubkey,
+    customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
+    base_out: u128,
+    max_quote_in: u128,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::BuyExactOut {
+            base_out,
+            max_quote_in,
+        },
+        buy_accounts(
+            creator_address,
+            state_address,
+            mint_address,
+            token_pool_address,
+            quote_pool_address,
+            beneficiary_address,
+            customer_address,
+            customer_token_associated_address,
+            customer_quote_address,
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn sell_exact_out(
+    creator_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    customer_address: &Pubkey,
+    customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
+    quote_out: u128,
+    max_base_in: u128,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::SellExactOut {
+            quote_out,
+            max_base_in,
+        },
+        sell_accounts(
+            creator_address,
+            state_address,
+            mint_address,
+            token_pool_address,
+            quote_pool_address,
+            beneficiary_address,
+            customer_address,
+            customer_token_associated_address,
+            customer_quote_address,
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn add_liquidity(
+    state_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    lp_mint_address: &Pubkey,
+    provider_address: &Pubkey,
+    provider_token_associated_address: &Pubkey,
+    provider_lp_associated_address: &Pubkey,
+    provider_quote_address: &Pubkey,
+    quote_amount: u128,
+    max_base_in: u128,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::AddLiquidity {
+            quote_amount,
+            max_base_in,
+        },
+        liquidity_accounts(
+            state_address,
+            token_pool_address,
+            quote_pool_address,
+            lp_mint_address,
+            provider_address,
+            provider_token_associated_address,
+            provider_lp_associated_address,
+            provider_quote_address,
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn remove_liquidity(
+    state_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    lp_mint_address: &Pubkey,
+    provider_address: &Pubkey,
+    provider_token_associated_address: &Pubkey,
+    provider_lp_associated_address: &Pubkey,
+    provider_quote_address: &Pubkey,
+    lp_amount: u128,
+    min_base_out: u128,
+    min_quote_out: u128,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::RemoveLiquidity {
+            lp_amount,
+            min_base_out,
+            min_quote_out,
+        },
+        liquidity_accounts(
+            state_address,
+            token_pool_address,
+            quote_pool_address,
+            lp_mint_address,
+            provider_address,
+            provider_token_associated_address,
+            provider_lp_associated_address,
+            provider_quote_address,
+        ),
+    )
+}
+
+pub fn set_beneficiary(
+    authority_address: &Pubkey,
+    state_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::SetBeneficiary(*beneficiary_address),
+        admin_accounts(authority_address, state_address),
+    )
+}
+
+pub fn pause(authority_address: &Pubkey, state_address: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::Pause,
+        admin_accounts(authority_address, state_address),
+    )
+}
+
+pub fn resume(authority_address: &Pubkey, state_address: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::Resume,
+        admin_accounts(authority_address, state_address),
+    )
+}
+
+pub fn halt_buys_only(authority_address: &Pubkey, state_address: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::HaltBuysOnly,
+        admin_accounts(authority_address, state_address),
+    )
+}
+
+pub fn halt_sells_only(authority_address: &Pubkey, state_address: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::HaltSellsOnly,
+        admin_accounts(authority_address, state_address),
+    )
+}
+
+pub fn propose_authority(
+    authority_address: &Pubkey,
+    state_address: &Pubkey,
+    new_authority_address: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::ProposeAuthority(*new_authority_address),
+        admin_accounts(authority_address, state_address),
+    )
+}
+
+pub fn accept_authority(new_authority_address: &Pubkey, state_address: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::AcceptAuthority,
+        admin_accounts(new_authority_address, state_address),
+    )
+}
+
+pub fn graduate(
+    authority_address: &Pubkey,
+    state_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    base_destination_address: &Pubkey,
+    quote_destination_address: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::Graduate,
         vec![
-            AccountMeta::new(*authority_address, true),
+            AccountMeta::new_readonly(*authority_address, true),
+            AccountMeta::new(*state_address, false),
+            AccountMeta::new(*token_pool_address, false),
+            AccountMeta::new(*quote_pool_address, false),
+            AccountMeta::new(*base_destination_address, false),
+            AccountMeta::new(*quote_destination_address, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+pub fn skim(
+    authority_address: &Pubkey,
+    state_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    base_destination_address: &Pubkey,
+    quote_destination_address: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::Skim,
+        vec![
+            AccountMeta::new_readonly(*authority_address, true),
+            AccountMeta::new(*state_address, false),
+            AccountMeta::new(*token_pool_address, false),
+            AccountMeta::new(*quote_pool_address, false),
+            AccountMeta::new(*base_destination_address, false),
+            AccountMeta::new(*quote_destination_address, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn close_pool(
+    authority_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    lp_mint_address: &Pubkey,
+    destination_address: &Pubkey,
+    quote_destination_address: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::ClosePool,
+        vec![
+            AccountMeta::new_readonly(*authority_address, true),
             AccountMeta::new(*state_address, false),
             AccountMeta::new(*mint_address, false),
             AccountMeta::new(*token_pool_address, false),
-            AccountMeta::new(*native_pool_address, false),
-            AccountMeta::new(*beneficiary_address, false),
-            AccountMeta::new(*customer_address, false),
-            AccountMeta::new(*customer_token_associated_address, false),
+            AccountMeta::new(*quote_pool_address, false),
+            AccountMeta::new_readonly(*lp_mint_address, false),
+            AccountMeta::new(get_oracle_address(state_address), false),
+            AccountMeta::new(*destination_address, false),
+            AccountMeta::new(*quote_destination_address, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+pub fn migrate_state(
+    authority_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    lp_mint_address: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::MigrateState,
+        vec![
+            AccountMeta::new(*authority_address, true),
+            AccountMeta::new(*state_address, false),
+            AccountMeta::new_readonly(*mint_address, false),
+            AccountMeta::new(*lp_mint_address, false),
             AccountMeta::new_readonly(system_program::id(), false),
             AccountMeta::new_readonly(spl_token::id(), false),
             AccountMeta::new_readonly(sysvar::rent::id(), false),
         ],
     )
 }
+
+pub fn quote(
+    state_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    side: Side,
+    amount: u128,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::Quote { side, amount },
+        vec![
+            AccountMeta::new_readonly(*state_address, false),
+            AccountMeta::new_readonly(*quote_pool_address, false),
+        ],
+    )
+}
+
+pub fn initialize_oracle(payer_address: &Pubkey, state_address: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        id(),
+        &PoolInstruction::InitializeOracle,
+        vec![
+            AccountMeta::new(*payer_address, true),
+            AccountMeta::new_readonly(*state_address, false),
+            AccountMeta::new(get_oracle_address(state_address), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+fn admin_accounts(authority_address: &Pubkey, state_address: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(*authority_address, true),
+        AccountMeta::new(*state_address, false),
+    ]
+}
+
+#[allow(clippy::too_many_arguments)]
+fn buy_accounts(
+    creator_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    customer_address: &Pubkey,
+    customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(*creator_address, false),
+        AccountMeta::new(*state_address, false),
+        AccountMeta::new(*mint_address, false),
+        AccountMeta::new(*token_pool_address, false),
+        AccountMeta::new(*quote_pool_address, false),
+        AccountMeta::new(*beneficiary_address, false),
+        AccountMeta::new(*customer_address, true),
+        AccountMeta::new(*customer_token_associated_address, false),
+        // the customer itself when the pool is quoted in lamports
+        AccountMeta::new(*customer_quote_address, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(get_oracle_address(state_address), false),
+    ]
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sell_accounts(
+    creator_address: &Pubkey,
+    state_address: &Pubkey,
+    mint_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    beneficiary_address: &Pubkey,
+    customer_address: &Pubkey,
+    customer_token_associated_address: &Pubkey,
+    customer_quote_address: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(*creator_address, false),
+        AccountMeta::new(*state_address, false),
+        AccountMeta::new(*mint_address, false),
+        AccountMeta::new(*token_pool_address, false),
+        AccountMeta::new(*quote_pool_address, false),
+        AccountMeta::new(*beneficiary_address, false),
+        AccountMeta::new(*customer_address, true),
+        AccountMeta::new(*customer_token_associated_address, false),
+        AccountMeta::new(*customer_quote_address, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(get_oracle_address(state_address), false),
+    ]
+}
+
+#[allow(clippy::too_many_arguments)]
+fn liquidity_accounts(
+    state_address: &Pubkey,
+    token_pool_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    lp_mint_address: &Pubkey,
+    provider_address: &Pubkey,
+    provider_token_associated_address: &Pubkey,
+    provider_lp_associated_address: &Pubkey,
+    provider_quote_address: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*state_address, false),
+        AccountMeta::new(*token_pool_address, false),
+        AccountMeta::new(*quote_pool_address, false),
+        AccountMeta::new(*lp_mint_address, false),
+        AccountMeta::new(*provider_address, true),
+        AccountMeta::new(*provider_token_associated_address, false),
+        AccountMeta::new(*provider_lp_associated_address, false),
+        AccountMeta::new(*provider_quote_address, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]
+}
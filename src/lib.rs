@@ -1,6 +1,9 @@
-mod balance;
+pub mod accounts;
+pub mod balance;
 pub mod error;
+pub mod event;
 pub mod instruction;
+pub mod math;
 pub mod processor;
 pub mod state;
 
@@ -11,49 +14,177 @@ solana_program::declare_id!("EjhMW84ENMdycHT2vtY8GdvvkcJrbZW6ohmvB72fLGqo");
 // ----------------------------------------------------------------------------
 // misc
 
+// internal precision of base amounts, one whole token regardless of the mint's decimals
 pub const BASE_UNIT: u128 = 1000000000000000000;
+pub const BASE_DECIMALS: u8 = 18;
+
+// unit of lamport-quoted pools, SPL-quoted pools use their mint's decimals instead
 pub const QUOTE_UNIT: u128 = 1000000000;
+pub const NATIVE_QUOTE_DECIMALS: u8 = 9;
+
+// fees are expressed in basis points of the quote amount
+pub const FEE_DENOMINATOR: u128 = 10000;
+pub const MAX_FEE_BPS: u16 = 1000;
+
+// LP shares are minted with the same precision as lamports
+pub const LP_DECIMALS: u8 = 9;
+
+// ----------------------------------------------------------------------------
+// pool
+
+/// Index of the pools created by the first release, whose addresses derive from the creator
+/// alone; never given to new pools
+pub const LEGACY_POOL_INDEX: u64 = u64::MAX;
+
+/// Seed of the pool index in every pool address, empty for legacy pools, which leaves
+/// their addresses as they were before pools were indexed
+pub fn get_pool_index_seed(pool_index: u64) -> Vec<u8> {
+    if pool_index == LEGACY_POOL_INDEX {
+        vec![]
+    } else {
+        pool_index.to_le_bytes().to_vec()
+    }
+}
+
+/// Every account address of a single pool, derived from its creator and index
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolAddresses {
+    pub state: Pubkey,
+    pub mint: Pubkey,
+    pub token_pool: Pubkey,
+    pub native_pool: Pubkey,
+    pub lp_mint: Pubkey,
+    pub quote_vault: Pubkey,
+    pub oracle: Pubkey,
+}
+
+pub fn get_pool_addresses(payer_address: &Pubkey, pool_index: u64) -> PoolAddresses {
+    PoolAddresses {
+        state: get_state_address(payer_address, pool_index),
+        mint: get_mint_address(payer_address, pool_index),
+        token_pool: get_token_pool_address(payer_address, pool_index),
+        native_pool: get_native_pool_address(payer_address, pool_index),
+        lp_mint: get_lp_mint_address(payer_address, pool_index),
+        quote_vault: get_quote_vault_address(payer_address, pool_index),
+        oracle: get_oracle_address(&get_state_address(payer_address, pool_index)),
+    }
+}
 
 // ----------------------------------------------------------------------------
 // state
 
-pub fn get_state_address(payer_address: &Pubkey) -> Pubkey {
-    get_state_address_with_seed(payer_address).0
+pub fn get_state_address(payer_address: &Pubkey, pool_index: u64) -> Pubkey {
+    get_state_address_with_seed(payer_address, pool_index).0
 }
 
-pub fn get_state_address_with_seed(payer_address: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[&payer_address.to_bytes(), br"state"], &id())
+pub fn get_state_address_with_seed(payer_address: &Pubkey, pool_index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &payer_address.to_bytes(),
+            &get_pool_index_seed(pool_index),
+            br"state",
+        ],
+        &id(),
+    )
 }
 
 // ----------------------------------------------------------------------------
 // mint
 
-pub fn get_mint_address(payer_address: &Pubkey) -> Pubkey {
-    get_mint_address_with_seed(payer_address).0
+pub fn get_mint_address(payer_address: &Pubkey, pool_index: u64) -> Pubkey {
+    get_mint_address_with_seed(payer_address, pool_index).0
 }
 
-pub fn get_mint_address_with_seed(payer_address: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[&payer_address.to_bytes(), br"mint"], &id())
+pub fn get_mint_address_with_seed(payer_address: &Pubkey, pool_index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &payer_address.to_bytes(),
+            &get_pool_index_seed(pool_index),
+            br"mint",
+        ],
+        &id(),
+    )
 }
 
 // ----------------------------------------------------------------------------
 // token pool
 
-pub fn get_token_pool_address(payer_address: &Pubkey) -> Pubkey {
-    get_token_pool_address_with_seed(payer_address).0
+pub fn get_token_pool_address(payer_address: &Pubkey, pool_index: u64) -> Pubkey {
+    get_token_pool_address_with_seed(payer_address, pool_index).0
 }
 
-pub fn get_token_pool_address_with_seed(payer_address: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[&payer_address.to_bytes(), br"token-pool"], &id())
+pub fn get_token_pool_address_with_seed(payer_address: &Pubkey, pool_index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &payer_address.to_bytes(),
+            &get_pool_index_seed(pool_index),
+            br"token-pool",
+        ],
+        &id(),
+    )
 }
 
 // ----------------------------------------------------------------------------
 // native pool
 
-pub fn get_native_pool_address(payer_address: &Pubkey) -> Pubkey {
-    get_native_pool_address_with_seed(payer_address).0
+pub fn get_native_pool_address(payer_address: &Pubkey, pool_index: u64) -> Pubkey {
+    get_native_pool_address_with_seed(payer_address, pool_index).0
+}
+
+pub fn get_native_pool_address_with_seed(payer_address: &Pubkey, pool_index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &payer_address.to_bytes(),
+            &get_pool_index_seed(pool_index),
+            br"native-pool",
+        ],
+        &id(),
+    )
+}
+
+// ----------------------------------------------------------------------------
+// lp mint
+
+pub fn get_lp_mint_address(payer_address: &Pubkey, pool_index: u64) -> Pubkey {
+    get_lp_mint_address_with_seed(payer_address, pool_index).0
+}
+
+pub fn get_lp_mint_address_with_seed(payer_address: &Pubkey, pool_index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &payer_address.to_bytes(),
+            &get_pool_index_seed(pool_index),
+            br"lp-mint",
+        ],
+        &id(),
+    )
+}
+
+// ----------------------------------------------------------------------------
+// quote vault
+
+pub fn get_quote_vault_address(payer_address: &Pubkey, pool_index: u64) -> Pubkey {
+    get_quote_vault_address_with_seed(payer_address, pool_index).0
+}
+
+pub fn get_quote_vault_address_with_seed(payer_address: &Pubkey, pool_index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            &payer_address.to_bytes(),
+            &get_pool_index_seed(pool_index),
+            br"quote-vault",
+        ],
+        &id(),
+    )
+}
+
+// ----------------------------------------------------------------------------
+// oracle
+
+pub fn get_oracle_address(state_address: &Pubkey) -> Pubkey {
+    get_oracle_address_with_seed(state_address).0
 }
 
-pub fn get_native_pool_address_with_seed(payer_address: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[&payer_address.to_bytes(), br"native-pool"], &id())
+pub fn get_oracle_address_with_seed(state_address: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[&state_address.to_bytes(), br"oracle"], &id())
 }
@@ -0,0 +1,210 @@
+// Integer math with explicit rounding; every failure surfaces as `IWRError::Overflow`
+use crate::error::IWRError;
+
+pub use self::wide::U256;
+
+mod wide {
+    // the macro expansion trips lints we don't control
+    #![allow(clippy::assign_op_pattern, clippy::ptr_offset_with_cast)]
+    use uint::construct_uint;
+
+    construct_uint! {
+        /// Wide enough for the product of any two u128 values
+        pub struct U256(4);
+    }
+}
+
+// ----------------------------------------------------------------------------
+// checked arithmetic
+
+pub fn add(a: u128, b: u128) -> Result<u128, IWRError> {
+    a.checked_add(b).ok_or(IWRError::Overflow)
+}
+
+pub fn sub(a: u128, b: u128) -> Result<u128, IWRError> {
+    a.checked_sub(b).ok_or(IWRError::Overflow)
+}
+
+pub fn mul(a: u128, b: u128) -> Result<u128, IWRError> {
+    a.checked_mul(b).ok_or(IWRError::Overflow)
+}
+
+/// Division rounded down, failing on a zero denominator
+pub fn div(numerator: u128, denominator: u128) -> Result<u128, IWRError> {
+    numerator.checked_div(denominator).ok_or(IWRError::Overflow)
+}
+
+/// Division rounded up, failing on a zero denominator
+pub fn div_ceil(numerator: u128, denominator: u128) -> Result<u128, IWRError> {
+    let quotient = div(numerator, denominator)?;
+
+    Ok(if numerator % denominator > 0 {
+        quotient + 1
+    } else {
+        quotient
+    })
+}
+
+/// `a * b / denominator` rounded down; only the result has to fit in u128
+pub fn mul_div_floor(a: u128, b: u128, denominator: u128) -> Result<u128, IWRError> {
+    if denominator == 0 {
+        return Err(IWRError::Overflow);
+    }
+
+    narrow(U256::from(a) * U256::from(b) / U256::from(denominator))
+}
+
+/// `a * b / denominator` rounded up; only the result has to fit in u128
+pub fn mul_div_ceil(a: u128, b: u128, denominator: u128) -> Result<u128, IWRError> {
+    if denominator == 0 {
+        return Err(IWRError::Overflow);
+    }
+
+    let (quotient, remainder) = (U256::from(a) * U256::from(b)).div_mod(U256::from(denominator));
+
+    if remainder.is_zero() {
+        narrow(quotient)
+    } else {
+        narrow(quotient + 1)
+    }
+}
+
+fn narrow(value: U256) -> Result<u128, IWRError> {
+    if value > U256::from(u128::MAX) {
+        return Err(IWRError::Overflow);
+    }
+
+    Ok(value.as_u128())
+}
+
+// ----------------------------------------------------------------------------
+// fixed-point helpers, 1e18 = 1.0
+
+pub(crate) const WAD: u128 = 1000000000000000000;
+const LN_2_WAD: u128 = 693147180559945309;
+
+/// e^x for `x` in WAD, rounded down
+pub(crate) fn exp_wad(x: u128) -> Result<u128, IWRError> {
+    // e^x = 2^k * e^r, with r < ln 2 keeping the series short
+    let k = x / LN_2_WAD;
+    let r = x % LN_2_WAD;
+
+    let mut sum = WAD;
+    let mut term = WAD;
+    let mut i = 1;
+    while term > 0 {
+        term = term * r / (i * WAD);
+        sum += term;
+        i += 1;
+    }
+
+    if k > sum.leading_zeros() as u128 {
+        return Err(IWRError::Overflow);
+    }
+
+    Ok(sum << k)
+}
+
+/// ln(x) for `x` >= 1.0 in WAD, rounded down
+pub(crate) fn ln_wad(x: u128) -> Result<u128, IWRError> {
+    if x < WAD {
+        return Err(IWRError::Overflow);
+    }
+
+    // ln x = k * ln 2 + ln y, with y in [1, 2)
+    let k = 127 - (x / WAD).leading_zeros() as u128;
+    let y = x >> k;
+
+    // ln y = 2 * atanh(z), z = (y - 1) / (y + 1)
+    let z = (y - WAD) * WAD / (y + WAD);
+    let z_squared = z * z / WAD;
+
+    let mut sum = 0;
+    let mut term = z;
+    let mut i = 1;
+    while term > 0 {
+        sum += term / i;
+        term = term * z_squared / WAD;
+        i += 2;
+    }
+
+    Ok(k * LN_2_WAD + 2 * sum)
+}
+
+/// Square root rounded down
+pub(crate) fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    let mut x = 1u128 << ((128 - n.leading_zeros() + 1) / 2);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul_div() {
+        assert_eq!(mul_div_floor(7, 3, 2), Ok(10));
+        assert_eq!(mul_div_ceil(7, 3, 2), Ok(11));
+        assert_eq!(mul_div_floor(6, 3, 2), Ok(9));
+        assert_eq!(mul_div_ceil(6, 3, 2), Ok(9));
+        assert_eq!(mul_div_floor(0, 3, 2), Ok(0));
+        assert_eq!(mul_div_ceil(0, 3, 2), Ok(0));
+
+        // intermediate products beyond u128 are fine as long as the result fits
+        assert_eq!(
+            mul_div_floor(u128::MAX, u128::MAX, u128::MAX),
+            Ok(u128::MAX)
+        );
+        assert_eq!(mul_div_ceil(u128::MAX, u128::MAX, u128::MAX), Ok(u128::MAX));
+        assert_eq!(mul_div_floor(u128::MAX, 2, 3), Ok(u128::MAX / 3 * 2));
+        assert_eq!(mul_div_ceil(u128::MAX, 2, 4), Ok(u128::MAX / 2 + 1));
+
+        assert_eq!(mul_div_floor(u128::MAX, 2, 1), Err(IWRError::Overflow));
+        assert_eq!(
+            mul_div_ceil(u128::MAX, u128::MAX, u128::MAX - 1),
+            Err(IWRError::Overflow)
+        );
+        assert_eq!(mul_div_floor(1, 1, 0), Err(IWRError::Overflow));
+        assert_eq!(mul_div_ceil(1, 1, 0), Err(IWRError::Overflow));
+
+        assert_eq!(div_ceil(1, 0), Err(IWRError::Overflow));
+        assert_eq!(div_ceil(u128::MAX, 1), Ok(u128::MAX));
+        assert_eq!(div_ceil(u128::MAX, 2), Ok(u128::MAX / 2 + 1));
+    }
+
+    #[test]
+    fn test_fixed_point_helpers() {
+        let e = 2718281828459045235u128;
+
+        assert_eq!(exp_wad(0), Ok(WAD));
+        assert!(exp_wad(WAD).unwrap().abs_diff(e) < 100);
+        assert_eq!(ln_wad(WAD), Ok(0));
+        assert!(ln_wad(e).unwrap().abs_diff(WAD) < 100);
+
+        for x in [1u128, WAD / 3, WAD, 5 * WAD, 40 * WAD] {
+            assert!(ln_wad(exp_wad(x).unwrap()).unwrap().abs_diff(x) < 100);
+        }
+
+        assert_eq!(exp_wad(100 * WAD), Err(IWRError::Overflow));
+        assert_eq!(exp_wad(u128::MAX), Err(IWRError::Overflow));
+        assert_eq!(ln_wad(WAD - 1), Err(IWRError::Overflow));
+
+        for n in [0u128, 1, 2, 3, 4, 15, 16, 17, u64::MAX as u128, u128::MAX] {
+            let root = isqrt(n);
+            assert!(root * root <= n);
+            assert!((root + 1)
+                .checked_mul(root + 1)
+                .map_or(true, |square| square > n));
+        }
+    }
+}
@@ -1,26 +1,32 @@
-use crate::balance::Balance;
+use crate::accounts::{
+    load_state, AdminAccounts, CloseAccounts, InitializeAccounts, LiquidityAccounts,
+    MigrateAccounts, OracleAccounts, QuoteAccounts, SwapAccounts, WithdrawAccounts,
+};
+use crate::balance::{Balance, Curve, CurveType};
 use crate::error::IWRError;
-use crate::instruction::PoolInstruction;
-use crate::state::State;
+use crate::event::{AdminChange, PoolEvent};
+use crate::instruction::{PoolInstruction, QuoteResult, Side};
+use crate::math::{add, div_ceil, mul, mul_div_ceil, mul_div_floor, sub};
+use crate::state::{Fees, Observation, Oracle, PoolStatus, State, StateV0};
 use crate::{
-    get_mint_address, get_mint_address_with_seed, get_native_pool_address_with_seed,
-    get_state_address_with_seed, get_token_pool_address, get_token_pool_address_with_seed, id,
-    BASE_UNIT,
+    get_lp_mint_address_with_seed, get_mint_address_with_seed, get_pool_index_seed,
+    get_state_address_with_seed, BASE_DECIMALS, BASE_UNIT, FEE_DENOMINATOR, LEGACY_POOL_INDEX,
+    LP_DECIMALS, NATIVE_QUOTE_DECIMALS,
 };
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::account_info::{next_account_info, AccountInfo};
+use solana_program::account_info::AccountInfo;
+use solana_program::clock::Clock;
 use solana_program::entrypoint;
 use solana_program::entrypoint::ProgramResult;
 use solana_program::msg;
-use solana_program::program::{invoke, invoke_signed};
+use solana_program::program::{invoke, invoke_signed, set_return_data};
 use solana_program::program_error::ProgramError;
-use solana_program::program_pack::{IsInitialized, Pack};
+use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
 use solana_program::rent::Rent;
 use solana_program::system_instruction;
 use solana_program::sysvar::Sysvar;
-use spl_associated_token_account::get_associated_token_address;
-use spl_token::solana_program::program_option::COption;
+use std::convert::TryFrom;
 
 // entrypoint! macro tells Solana that this function is an
 // entry point for this program
@@ -31,567 +37,2233 @@ pub fn process_instruction(
     instruction_data: &[u8],
 ) -> ProgramResult {
     let instruction = PoolInstruction::try_from_slice(instruction_data)?;
-    let account_info_iter = &mut accounts.iter();
 
     match instruction {
         PoolInstruction::Initialize {
+            pool_index,
             total_token_supply,
             decimals,
             initial_quote_amount,
-        } => {
-            let payer_info = next_account_info(account_info_iter)?;
-            let state_info = next_account_info(account_info_iter)?;
-            let mint_info = next_account_info(account_info_iter)?;
-            let token_pool_info = next_account_info(account_info_iter)?;
-            let native_pool_info = next_account_info(account_info_iter)?;
-            let system_program_info = next_account_info(account_info_iter)?;
-            let token_program_info = next_account_info(account_info_iter)?;
-            let rent_sysvar_info = next_account_info(account_info_iter)?;
-            let rent = &Rent::from_account_info(rent_sysvar_info)?;
-
-            // ----------------------------------------------------------------------------
-            // state account
-
-            let (state_address, state_bump_seed) = get_state_address_with_seed(payer_info.key);
-
-            if state_address != *state_info.key {
-                msg!("Error: state address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
-            }
+            fees,
+            curve,
+            virtual_balance,
+            graduation_threshold,
+            beneficiary,
+        } => process_initialize(
+            program_id,
+            accounts,
+            pool_index,
+            total_token_supply,
+            decimals,
+            initial_quote_amount,
+            fees,
+            curve,
+            virtual_balance,
+            graduation_threshold,
+            beneficiary,
+        ),
+        PoolInstruction::Buy(quote_amount) => process_buy(
+            program_id,
+            accounts,
+            SwapAmount::ExactIn {
+                amount: quote_amount,
+                min_out: 0,
+            },
+        ),
+        PoolInstruction::Sell(base_amount) => process_sell(
+            program_id,
+            accounts,
+            SwapAmount::ExactIn {
+                amount: base_amount,
+                min_out: 0,
+            },
+        ),
+        PoolInstruction::BuyWithSlippage {
+            quote_amount,
+            min_base_out,
+        } => process_buy(
+            program_id,
+            accounts,
+            SwapAmount::ExactIn {
+                amount: quote_amount,
+                min_out: min_base_out,
+            },
+        ),
+        PoolInstruction::SellWithSlippage {
+            base_amount,
+            min_quote_out,
+        } => process_sell(
+            program_id,
+            accounts,
+            SwapAmount::ExactIn {
+                amount: base_amount,
+                min_out: min_quote_out,
+            },
+        ),
+        PoolInstruction::BuyExactOut {
+            base_out,
+            max_quote_in,
+        } => process_buy(
+            program_id,
+            accounts,
+            SwapAmount::ExactOut {
+                amount: base_out,
+                max_in: max_quote_in,
+            },
+        ),
+        PoolInstruction::SellExactOut {
+            quote_out,
+            max_base_in,
+        } => process_sell(
+            program_id,
+            accounts,
+            SwapAmount::ExactOut {
+                amount: quote_out,
+                max_in: max_base_in,
+            },
+        ),
+        PoolInstruction::SetBeneficiary(beneficiary) => {
+            process_set_beneficiary(program_id, accounts, beneficiary)
+        }
+        PoolInstruction::Pause => process_set_status(program_id, accounts, PoolStatus::Paused),
+        PoolInstruction::Resume => process_set_status(program_id, accounts, PoolStatus::Active),
+        PoolInstruction::HaltBuysOnly => {
+            process_set_status(program_id, accounts, PoolStatus::BuysHalted)
+        }
+        PoolInstruction::HaltSellsOnly => {
+            process_set_status(program_id, accounts, PoolStatus::SellsHalted)
+        }
+        PoolInstruction::ProposeAuthority(new_authority) => {
+            process_propose_authority(program_id, accounts, new_authority)
+        }
+        PoolInstruction::AcceptAuthority => process_accept_authority(program_id, accounts),
+        PoolInstruction::AddLiquidity {
+            quote_amount,
+            max_base_in,
+        } => process_add_liquidity(program_id, accounts, quote_amount, max_base_in),
+        PoolInstruction::RemoveLiquidity {
+            lp_amount,
+            min_base_out,
+            min_quote_out,
+        } => process_remove_liquidity(program_id, accounts, lp_amount, min_base_out, min_quote_out),
+        PoolInstruction::Graduate => process_graduate(program_id, accounts),
+        PoolInstruction::MigrateState => process_migrate_state(program_id, accounts),
+        PoolInstruction::Quote { side, amount } => {
+            process_quote(program_id, accounts, side, amount)
+        }
+        PoolInstruction::InitializeOracle => process_initialize_oracle(program_id, accounts),
+        PoolInstruction::Skim => process_skim(program_id, accounts),
+        PoolInstruction::ClosePool => process_close_pool(program_id, accounts),
+    }
+}
 
-            let state_signer_seeds: &[&[_]] =
-                &[&payer_info.key.to_bytes(), br"state", &[state_bump_seed]];
+/// Side of a swap fixed by the customer, along with their limit for the other side
+enum SwapAmount {
+    /// Spending exactly `amount`, expecting at least `min_out` in return
+    ExactIn { amount: u128, min_out: u128 },
+    /// Receiving exactly `amount`, spending at most `max_in` for it
+    ExactOut { amount: u128, max_in: u128 },
+}
 
-            // ----------------------------------------------------------------------------
-            // mint account
+#[allow(clippy::too_many_arguments)]
+fn process_initialize(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    pool_index: u64,
+    total_token_supply: u128,
+    decimals: u8,
+    initial_quote_amount: u128,
+    fees: Fees,
+    curve: CurveType,
+    virtual_balance: Balance,
+    graduation_threshold: u128,
+    beneficiary: Pubkey,
+) -> ProgramResult {
+    let InitializeAccounts {
+        payer_info,
+        state_info,
+        mint_info,
+        token_pool_info,
+        quote_pool_info,
+        lp_mint_info,
+        quote_mint_info,
+        payer_quote_info,
+        system_program_info,
+        token_program_info,
+        rent_sysvar_info,
+        state_bump_seed,
+        mint_bump_seed,
+        token_pool_bump_seed,
+        quote_pool_bump_seed,
+        quote_pool_seed,
+        lp_mint_bump_seed,
+    } = InitializeAccounts::load(accounts, pool_index)?;
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    let state_signer_seeds: &[&[_]] = &[
+        &payer_info.key.to_bytes(),
+        &get_pool_index_seed(pool_index),
+        br"state",
+        &[state_bump_seed],
+    ];
+
+    let mint_signer_seeds: &[&[_]] = &[
+        &payer_info.key.to_bytes(),
+        &get_pool_index_seed(pool_index),
+        br"mint",
+        &[mint_bump_seed],
+    ];
+
+    let token_pool_signer_seeds: &[&[_]] = &[
+        &payer_info.key.to_bytes(),
+        &get_pool_index_seed(pool_index),
+        br"token-pool",
+        &[token_pool_bump_seed],
+    ];
+
+    let quote_pool_signer_seeds: &[&[_]] = &[
+        &payer_info.key.to_bytes(),
+        &get_pool_index_seed(pool_index),
+        quote_pool_seed,
+        &[quote_pool_bump_seed],
+    ];
+
+    let lp_mint_signer_seeds: &[&[_]] = &[
+        &payer_info.key.to_bytes(),
+        &get_pool_index_seed(pool_index),
+        br"lp-mint",
+        &[lp_mint_bump_seed],
+    ];
+
+    let quote_decimals = if *quote_mint_info.key == spl_token::native_mint::id() {
+        NATIVE_QUOTE_DECIMALS
+    } else {
+        spl_token::state::Mint::unpack(&quote_mint_info.data.borrow())?.decimals
+    };
+
+    // ----------------------------------------------------------------------------
+    // processing
+
+    // creating the account would fail as well, just without saying why
+    let state_version = match state_info.data.borrow().first() {
+        Some(version) => *version,
+        None => State::UNINITIALIZED_VERSION,
+    };
+
+    if state_info.owner == program_id && state_version != State::UNINITIALIZED_VERSION {
+        msg!("Error: pool is already initialized");
+        return Err(ProgramError::Custom(IWRError::AlreadyInitialized as u32));
+    }
 
-            let (mint_address, mint_bump_seed) = get_mint_address_with_seed(payer_info.key);
+    if pool_index == LEGACY_POOL_INDEX {
+        msg!("Error: pool index {} is reserved", pool_index);
+        return Err(ProgramError::Custom(IWRError::ReservedPoolIndex as u32));
+    }
 
-            if mint_address != *mint_info.key {
-                msg!("Error: mint address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
-            }
+    if decimals > BASE_DECIMALS {
+        msg!("Error: mint decimals exceed the base precision");
+        return Err(ProgramError::Custom(IWRError::InvalidDecimals as u32));
+    }
 
-            let mint_signer_seeds: &[&[_]] =
-                &[&payer_info.key.to_bytes(), br"mint", &[mint_bump_seed]];
+    if !fees.is_valid() {
+        msg!("Error: fees exceed the protocol maximum");
+        return Err(ProgramError::Custom(IWRError::FeeTooHigh as u32));
+    }
 
-            // ----------------------------------------------------------------------------
-            // token pool account
+    if !curve.is_valid() {
+        msg!("Error: invalid curve parameters");
+        return Err(ProgramError::Custom(IWRError::InvalidCurve as u32));
+    }
 
-            let (token_pool_address, token_pool_bump_seed) =
-                get_token_pool_address_with_seed(payer_info.key);
+    // virtual reserves only shape the constant product price
+    if virtual_balance != Balance::default() && curve != CurveType::ConstantProduct {
+        msg!("Error: virtual reserves require a constant product curve");
+        return Err(ProgramError::Custom(
+            IWRError::InvalidVirtualReserves as u32,
+        ));
+    }
 
-            if token_pool_address != *token_pool_info.key {
-                msg!("Error: token pool address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
-            }
+    let clock = Clock::get()?;
+    let mut initial_state = State {
+        version: State::CURRENT_VERSION,
+        creator: *payer_info.key,
+        pool_index,
+        authority: *payer_info.key,
+        pending_authority: Pubkey::default(),
+        mint_authority: *mint_info.key,
+        base_pool_authority: *token_pool_info.key,
+        quote_pool_authority: *quote_pool_info.key,
+        beneficiary,
+        base_decimals: decimals,
+        quote_mint: *quote_mint_info.key,
+        quote_decimals,
+        balance: Balance {
+            base: 0,
+            quote: initial_quote_amount,
+        },
+        base_dust: 0,
+        virtual_balance,
+        curve,
+        fees,
+        status: PoolStatus::Active,
+        graduation_threshold,
+        // the seed liquidity is locked, so its shares are never minted
+        lp_supply: initial_quote_amount,
+        oracle: Observation {
+            timestamp: clock.unix_timestamp,
+            slot: clock.slot,
+            price_cumulative: 0,
+        },
+        legacy_scale: false,
+    };
+
+    initial_state.balance.base = mul(total_token_supply, initial_state.base_scale())?;
+
+    // a constant product pool needs both reserves to have a price
+    if initial_state.curve == CurveType::ConstantProduct {
+        let balance = initial_state.effective_balance()?;
+
+        if balance.base == 0 || balance.quote == 0 {
+            msg!("Error: pool reserves leave no starting price");
+            return Err(ProgramError::Custom(
+                IWRError::InvalidVirtualReserves as u32,
+            ));
+        }
+    }
 
-            let token_pool_signer_seeds: &[&[_]] = &[
-                &payer_info.key.to_bytes(),
-                br"token-pool",
-                &[token_pool_bump_seed],
-            ];
+    // sized for the widest curve plus room for later fields, not the in-memory layout
+    let required_data_size = State::ACCOUNT_LEN;
+
+    msg!("creating state account");
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_info.key,
+            state_info.key,
+            1.max(Rent::default().minimum_balance(required_data_size)),
+            required_data_size as u64,
+            program_id,
+        ),
+        &[
+            payer_info.clone(),
+            state_info.clone(),
+            system_program_info.clone(),
+            rent_sysvar_info.clone(),
+        ],
+        &[state_signer_seeds],
+    )?;
+
+    msg!("creating mint");
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_info.key,
+            mint_info.key,
+            1.max(rent.minimum_balance(spl_token::state::Mint::get_packed_len())),
+            spl_token::state::Mint::get_packed_len() as u64,
+            &spl_token::id(),
+        ),
+        &[
+            payer_info.clone(),
+            mint_info.clone(),
+            rent_sysvar_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[mint_signer_seeds],
+    )?;
+
+    msg!("initializing mint");
+    invoke(
+        &spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            mint_info.key,
+            token_pool_info.key,
+            None,
+            decimals,
+        )?,
+        &[
+            mint_info.clone(),
+            token_program_info.clone(),
+            rent_sysvar_info.clone(),
+        ],
+    )?;
+
+    msg!("creating token pool account");
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_info.key,
+            token_pool_info.key,
+            1.max(rent.minimum_balance(spl_token::state::Account::get_packed_len())),
+            spl_token::state::Account::get_packed_len() as u64,
+            &spl_token::id(),
+        ),
+        &[
+            payer_info.clone(),
+            token_pool_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[token_pool_signer_seeds],
+    )?;
+
+    msg!("initializing token pool account");
+    invoke(
+        &spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            token_pool_info.key,
+            mint_info.key,
+            token_pool_info.key,
+        )?,
+        &[
+            token_pool_info.clone(),
+            token_program_info.clone(),
+            rent_sysvar_info.clone(),
+            mint_info.clone(),
+        ],
+    )?;
+
+    msg!("minting tokens: {}", total_token_supply);
+    invoke_signed(
+        &spl_token::instruction::mint_to(
+            &spl_token::id(),
+            mint_info.key,
+            token_pool_info.key,
+            token_pool_info.key,
+            &[],
+            to_u64(total_token_supply)?,
+        )?,
+        &[
+            mint_info.clone(),
+            token_pool_info.clone(),
+            token_program_info.clone(),
+        ],
+        &[mint_signer_seeds, token_pool_signer_seeds],
+    )?;
+
+    if initial_state.is_native_quote() {
+        msg!("creating native pool account");
+        invoke_signed(
+            &system_instruction::create_account(
+                payer_info.key,
+                quote_pool_info.key,
+                1.max(Rent::default().minimum_balance(0)),
+                0,
+                program_id,
+            ),
+            &[
+                payer_info.clone(),
+                quote_pool_info.clone(),
+                system_program_info.clone(),
+                rent_sysvar_info.clone(),
+            ],
+            &[quote_pool_signer_seeds],
+        )?;
+    } else {
+        msg!("creating quote vault account");
+        invoke_signed(
+            &system_instruction::create_account(
+                payer_info.key,
+                quote_pool_info.key,
+                1.max(rent.minimum_balance(spl_token::state::Account::get_packed_len())),
+                spl_token::state::Account::get_packed_len() as u64,
+                &spl_token::id(),
+            ),
+            &[
+                payer_info.clone(),
+                quote_pool_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[quote_pool_signer_seeds],
+        )?;
+
+        msg!("initializing quote vault account");
+        invoke(
+            &spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                quote_pool_info.key,
+                quote_mint_info.key,
+                quote_pool_info.key,
+            )?,
+            &[
+                quote_pool_info.clone(),
+                token_program_info.clone(),
+                rent_sysvar_info.clone(),
+                quote_mint_info.clone(),
+            ],
+        )?;
+    }
 
-            // ----------------------------------------------------------------------------
-            // native pool account
+    msg!("funding quote pool account");
+    transfer_quote(
+        &initial_state,
+        payer_quote_info,
+        payer_info,
+        quote_pool_info,
+        system_program_info,
+        token_program_info,
+        initial_quote_amount,
+    )?;
+
+    msg!("creating lp mint");
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_info.key,
+            lp_mint_info.key,
+            1.max(rent.minimum_balance(spl_token::state::Mint::get_packed_len())),
+            spl_token::state::Mint::get_packed_len() as u64,
+            &spl_token::id(),
+        ),
+        &[
+            payer_info.clone(),
+            lp_mint_info.clone(),
+            rent_sysvar_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[lp_mint_signer_seeds],
+    )?;
+
+    msg!("initializing lp mint");
+    invoke(
+        &spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            lp_mint_info.key,
+            lp_mint_info.key,
+            None,
+            LP_DECIMALS,
+        )?,
+        &[
+            lp_mint_info.clone(),
+            token_program_info.clone(),
+            rent_sysvar_info.clone(),
+        ],
+    )?;
+
+    initial_state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::Initialized {
+        state: *state_info.key,
+        creator: initial_state.creator,
+        pool_index,
+        authority: initial_state.authority,
+        quote_mint: initial_state.quote_mint,
+        curve: initial_state.curve,
+        balance: initial_state.balance,
+    }
+    .emit()?;
 
-            let (native_pool_address, native_pool_bump_seed) =
-                get_native_pool_address_with_seed(payer_info.key);
+    Ok(())
+}
 
-            if native_pool_address != *native_pool_info.key {
-                msg!("Error: native pool address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
+fn process_buy(program_id: &Pubkey, accounts: &[AccountInfo], swap: SwapAmount) -> ProgramResult {
+    let (
+        SwapAccounts {
+            creator_info,
+            state_info,
+            mint_info,
+            token_pool_info,
+            quote_pool_info,
+            beneficiary_info,
+            customer_info,
+            customer_token_associated_info,
+            customer_quote_info,
+            system_program_info,
+            token_program_info,
+            oracle_info,
+            mint_bump_seed,
+            token_pool_bump_seed,
+            ..
+        },
+        mut state,
+    ) = SwapAccounts::load(program_id, accounts)?;
+
+    let mint_signer_seeds: &[&[_]] = &[
+        &creator_info.key.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"mint",
+        &[mint_bump_seed],
+    ];
+
+    let token_pool_signer_seeds: &[&[_]] = &[
+        &creator_info.key.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"token-pool",
+        &[token_pool_bump_seed],
+    ];
+
+    // ----------------------------------------------------------------------------
+    // processing exchange
+
+    check_buys_allowed(&state)?;
+
+    // the price up to now is weighed before the trade moves it
+    update_oracle(program_id, &mut state, oracle_info)?;
+
+    // calculating exchange amounts, priced with the virtual reserves included
+    let balance = state.effective_balance()?;
+    let base_scale = state.base_scale();
+
+    let (quote_amount, base_amount) = match swap {
+        SwapAmount::ExactIn { amount, .. } => {
+            // only whole tokens can be transferred, the customer pays for those alone
+            let base_return = state
+                .curve
+                .calculate_base_for_quote_amount(&balance, amount)?;
+            let base_amount = base_return / base_scale * base_scale;
+
+            // the curve can never give out its entire base
+            if base_amount >= state.balance.base {
+                msg!("not enough tokens");
+                return Err(ProgramError::Custom(IWRError::NotEnoughTokens as u32));
             }
 
-            let native_pool_signer_seeds: &[&[_]] = &[
-                &payer_info.key.to_bytes(),
-                br"native-pool",
-                &[native_pool_bump_seed],
-            ];
-
-            // ----------------------------------------------------------------------------
-            // processing
-
-            let required_data_size = std::mem::size_of::<State>();
-
-            msg!("creating state account");
-            invoke_signed(
-                &system_instruction::create_account(
-                    payer_info.key,
-                    &state_address,
-                    1.max(Rent::default().minimum_balance(required_data_size)),
-                    required_data_size as u64,
-                    &program_id,
-                ),
-                &[
-                    payer_info.clone(),
-                    state_info.clone(),
-                    system_program_info.clone(),
-                    rent_sysvar_info.clone(),
-                ],
-                &[state_signer_seeds],
-            )?;
+            let quote_amount = state
+                .curve
+                .calculate_quote_for_base_out(&balance, base_amount)?;
 
-            msg!("creating mint");
-            invoke_signed(
-                &system_instruction::create_account(
-                    payer_info.key,
-                    &mint_address,
-                    1.max(rent.minimum_balance(spl_token::state::Mint::get_packed_len())),
-                    spl_token::state::Mint::get_packed_len() as u64,
-                    &spl_token::id(),
-                ),
-                &[
-                    payer_info.clone(),
-                    mint_info.clone(),
-                    rent_sysvar_info.clone(),
-                    system_program_info.clone(),
-                ],
-                &[mint_signer_seeds],
-            )?;
+            (quote_amount.min(amount), base_amount)
+        }
+        SwapAmount::ExactOut { amount, .. } => {
+            // only whole tokens can be transferred, rounding up in the pool's favor
+            let amount = mul(div_ceil(amount, base_scale)?, base_scale)?;
+
+            // the curve can never give out its entire base
+            if amount >= state.balance.base {
+                msg!("not enough tokens");
+                return Err(ProgramError::Custom(IWRError::NotEnoughTokens as u32));
+            }
 
-            msg!("initializing mint");
-            invoke(
-                &spl_token::instruction::initialize_mint(
-                    &spl_token::id(),
-                    mint_info.key,
-                    &token_pool_address,
-                    None,
-                    decimals,
-                )?,
-                &[
-                    mint_info.clone(),
-                    token_program_info.clone(),
-                    rent_sysvar_info.clone(),
-                ],
-            )?;
+            (
+                state.curve.calculate_quote_for_base_out(&balance, amount)?,
+                amount,
+            )
+        }
+    };
 
-            msg!("creating token pool account");
-            invoke_signed(
-                &system_instruction::create_account(
-                    payer_info.key,
-                    token_pool_info.key,
-                    1.max(rent.minimum_balance(spl_token::state::Account::get_packed_len())),
-                    spl_token::state::Account::get_packed_len() as u64,
-                    &spl_token::id(),
-                ),
-                &[
-                    payer_info.clone(),
-                    token_pool_info.clone(),
-                    system_program_info.clone(),
-                ],
-                &[token_pool_signer_seeds],
-            )?;
+    // charging for nothing, the quote would be lost to the customer
+    if base_amount == 0 {
+        msg!("Error: amount buys less than one whole token");
+        return Err(ProgramError::Custom(IWRError::AmountTooSmall as u32));
+    }
 
-            msg!("initializing token pool account");
-            invoke(
-                &spl_token::instruction::initialize_account(
-                    &spl_token::id(),
-                    token_pool_info.key,
-                    mint_info.key,
-                    token_pool_info.key,
-                )?,
-                &[
-                    token_pool_info.clone(),
-                    token_program_info.clone(),
-                    rent_sysvar_info.clone(),
-                    mint_info.clone(),
-                ],
-            )?;
+    let fee_amount = state.fees.calculate_buy_fee(quote_amount)?;
+    let lp_fee_amount = state.fees.calculate_lp_fee_share(fee_amount)?;
 
-            msg!("minting tokens: {}", total_token_supply);
-            invoke_signed(
-                &spl_token::instruction::mint_to(
-                    &spl_token::id(),
-                    mint_info.key,
-                    token_pool_info.key,
-                    token_pool_info.key,
-                    &[],
-                    total_token_supply as u64,
-                )?,
-                &[
-                    mint_info.clone(),
-                    token_pool_info.clone(),
-                    token_program_info.clone(),
-                ],
-                &[mint_signer_seeds, token_pool_signer_seeds],
-            )?;
+    // protecting the customer from trades that landed ahead of this one
+    match swap {
+        SwapAmount::ExactIn { min_out, .. } if base_amount < min_out => {
+            msg!(
+                "Error: base return {} is below the minimum of {}",
+                base_amount,
+                min_out
+            );
+            return Err(ProgramError::Custom(IWRError::SlippageExceeded as u32));
+        }
+        SwapAmount::ExactOut { max_in, .. } if add(quote_amount, fee_amount)? > max_in => {
+            msg!(
+                "Error: quote cost {} + fee {} is above the maximum of {}",
+                quote_amount,
+                fee_amount,
+                max_in
+            );
+            return Err(ProgramError::Custom(IWRError::SlippageExceeded as u32));
+        }
+        _ => {}
+    }
 
-            msg!("creating native pool account");
-            invoke_signed(
-                &system_instruction::create_account(
-                    payer_info.key,
-                    native_pool_info.key,
-                    1.max(Rent::default().minimum_balance(0)),
-                    0,
-                    &program_id,
-                ),
-                &[
-                    payer_info.clone(),
-                    native_pool_info.clone(),
-                    system_program_info.clone(),
-                    rent_sysvar_info.clone(),
-                ],
-                &[native_pool_signer_seeds],
-            )?;
+    // making sure that customer account has enough lamports for the amount and the fee with
+    // the rent buffer intact, token transfers check their own balances
+    if state.is_native_quote() {
+        let customer_account_minimum_rent =
+            &Rent::get()?.minimum_balance(customer_quote_info.data_len());
 
-            msg!("funding native pool account");
-            invoke(
-                &system_instruction::transfer(
-                    payer_info.key,
-                    native_pool_info.key,
-                    initial_quote_amount as u64,
-                ),
-                &[
-                    payer_info.clone(),
-                    native_pool_info.clone(),
-                    system_program_info.clone(),
-                ],
-            )?;
+        let quote_threshold = to_u64(add(quote_amount, fee_amount)?)?
+            .checked_add(*customer_account_minimum_rent)
+            .ok_or(IWRError::Overflow)?;
 
-            let initial_state = State {
-                authority: *payer_info.key,
-                mint_authority: *mint_info.key,
-                base_pool_authority: *token_pool_info.key,
-                quote_pool_authority: *native_pool_info.key,
-                balance: Balance {
-                    base: total_token_supply * BASE_UNIT,
-                    quote: initial_quote_amount,
-                },
-            };
-
-            state_info
-                .data
-                .borrow_mut()
-                .copy_from_slice(&initial_state.try_to_vec().unwrap());
+        // checking customer lamports
+        if customer_quote_info.lamports() < quote_threshold {
+            msg!("not enough lamports");
+            return Err(ProgramError::Custom(IWRError::NotEnoughLamports as u32));
         }
+    }
 
-        PoolInstruction::Buy(quote_amount) => {
-            let authority_info = next_account_info(account_info_iter)?;
-            let state_info = next_account_info(account_info_iter)?;
-            let mint_info = next_account_info(account_info_iter)?;
-            let token_pool_info = next_account_info(account_info_iter)?;
-            let native_pool_info = next_account_info(account_info_iter)?;
-            let beneficiary_info = next_account_info(account_info_iter)?;
-            let customer_info = next_account_info(account_info_iter)?;
-            let customer_token_associated_info = next_account_info(account_info_iter)?;
-            let system_program_info = next_account_info(account_info_iter)?;
-            let token_program_info = next_account_info(account_info_iter)?;
-
-            // ----------------------------------------------------------------------------
-            // state account
-
-            let (state_address, state_bump_seed) = get_state_address_with_seed(authority_info.key);
-
-            if state_address != *state_info.key {
-                msg!("Error: state address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
-            }
+    msg!(
+        "exchanging {} lamports for {} base",
+        quote_amount,
+        base_amount
+    );
+
+    msg!("debiting lamports: {} + fee {}", quote_amount, fee_amount);
+
+    // the liquidity providers' part of the fee stays in the pool
+    transfer_quote(
+        &state,
+        customer_quote_info,
+        customer_info,
+        quote_pool_info,
+        system_program_info,
+        token_program_info,
+        add(quote_amount, lp_fee_amount)?,
+    )?;
+
+    transfer_quote(
+        &state,
+        customer_quote_info,
+        customer_info,
+        beneficiary_info,
+        system_program_info,
+        token_program_info,
+        sub(fee_amount, lp_fee_amount)?,
+    )?;
+
+    msg!("crediting tokens: {}", base_amount);
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            &spl_token::id(),
+            token_pool_info.key,
+            customer_token_associated_info.key,
+            token_pool_info.key,
+            &[],
+            to_u64(base_amount / base_scale)?,
+        )?,
+        &[
+            token_program_info.clone(),
+            token_pool_info.clone(),
+            customer_token_associated_info.clone(),
+            token_pool_info.clone(),
+            mint_info.clone(),
+        ],
+        &[mint_signer_seeds, token_pool_signer_seeds],
+    )?;
+
+    // applying changes to the balance
+    state
+        .curve
+        .apply_buy(&mut state.balance, base_amount, quote_amount)?;
+
+    state.balance.quote = add(state.balance.quote, lp_fee_amount)?;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::Bought {
+        state: *state_info.key,
+        customer: *customer_info.key,
+        quote_amount,
+        base_amount,
+        fee: fee_amount,
+        balance: state.balance.clone(),
+    }
+    .emit()?;
 
-            let state_signer_seeds: &[&[_]] = &[
-                &authority_info.key.to_bytes(),
-                br"state",
-                &[state_bump_seed],
-            ];
+    PoolEvent::FeeCollected {
+        state: *state_info.key,
+        beneficiary: *beneficiary_info.key,
+        amount: sub(fee_amount, lp_fee_amount)?,
+        lp_amount: lp_fee_amount,
+    }
+    .emit()?;
 
-            // ----------------------------------------------------------------------------
-            // mint account
+    Ok(())
+}
 
-            let (mint_address, mint_bump_seed) = get_mint_address_with_seed(authority_info.key);
+fn process_sell(program_id: &Pubkey, accounts: &[AccountInfo], swap: SwapAmount) -> ProgramResult {
+    let (
+        SwapAccounts {
+            creator_info,
+            state_info,
+            mint_info,
+            token_pool_info,
+            quote_pool_info,
+            beneficiary_info,
+            customer_info,
+            customer_token_associated_info,
+            customer_quote_info,
+            token_program_info,
+            oracle_info,
+            mint_bump_seed,
+            token_pool_bump_seed,
+            quote_pool_bump_seed,
+            quote_pool_seed,
+            ..
+        },
+        mut state,
+    ) = SwapAccounts::load(program_id, accounts)?;
+
+    let mint_signer_seeds: &[&[_]] = &[
+        &creator_info.key.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"mint",
+        &[mint_bump_seed],
+    ];
+
+    let token_pool_signer_seeds: &[&[_]] = &[
+        &creator_info.key.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"token-pool",
+        &[token_pool_bump_seed],
+    ];
+
+    let quote_pool_signer_seeds: &[&[_]] = &[
+        &creator_info.key.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        quote_pool_seed,
+        &[quote_pool_bump_seed],
+    ];
+
+    // ----------------------------------------------------------------------------
+    // processing exchange
+
+    check_sells_allowed(&state)?;
+
+    // the price up to now is weighed before the trade moves it
+    update_oracle(program_id, &mut state, oracle_info)?;
+
+    // calculating exchange amounts, priced with the virtual reserves included
+    let balance = state.effective_balance()?;
+    let base_scale = state.base_scale();
+
+    let (base_amount, quote_return, fee_amount) = match swap {
+        SwapAmount::ExactIn { amount, .. } => {
+            // only whole tokens can be transferred, so only those are sold
+            let amount = amount / base_scale * base_scale;
+
+            let quote_return = state
+                .curve
+                .calculate_quote_for_base_amount(&balance, amount)?;
+
+            (
+                amount,
+                quote_return,
+                state.fees.calculate_sell_fee(quote_return)?,
+            )
+        }
+        SwapAmount::ExactOut { amount, .. } => {
+            // the fee is taken on top, so the customer receives exactly the requested amount
+            let quote_return = state.fees.calculate_quote_before_sell_fee(amount)?;
 
-            if mint_address != *mint_info.key {
-                msg!("Error: mint address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
+            if quote_return >= state.balance.quote {
+                msg!("not enough lamports");
+                return Err(ProgramError::Custom(IWRError::NotEnoughLamports as u32));
             }
 
-            let mint_signer_seeds: &[&[_]] =
-                &[&authority_info.key.to_bytes(), br"mint", &[mint_bump_seed]];
+            // only whole tokens can be transferred, rounding up in the pool's favor
+            let base_amount = state
+                .curve
+                .calculate_base_for_quote_out(&balance, quote_return)?;
+            let base_amount = mul(div_ceil(base_amount, base_scale)?, base_scale)?;
 
-            // ----------------------------------------------------------------------------
-            // token pool account
+            (base_amount, quote_return, sub(quote_return, amount)?)
+        }
+    };
 
-            let (token_pool_address, token_pool_bump_seed) =
-                get_token_pool_address_with_seed(authority_info.key);
+    // price curves and virtual reserves aren't bounded by the real balance on their own
+    if quote_return >= state.balance.quote {
+        msg!("not enough lamports");
+        return Err(ProgramError::Custom(IWRError::NotEnoughLamports as u32));
+    }
 
-            if token_pool_address != *token_pool_info.key {
-                msg!("Error: token pool address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
-            }
+    // whatever the books say, the payout has to exist in the quote pool, above the native
+    // pool's rent
+    if quote_pool_amount(&state, quote_pool_info)? < quote_return {
+        msg!("Error: quote pool can't cover the sell");
+        return Err(ProgramError::Custom(IWRError::NotEnoughLamports as u32));
+    }
+
+    let net_quote_return = sub(quote_return, fee_amount)?;
+    let lp_fee_amount = state.fees.calculate_lp_fee_share(fee_amount)?;
+
+    // protecting the customer from trades that landed ahead of this one
+    match swap {
+        SwapAmount::ExactIn { min_out, .. } if net_quote_return < min_out => {
+            msg!(
+                "Error: quote return {} is below the minimum of {}",
+                net_quote_return,
+                min_out
+            );
+            return Err(ProgramError::Custom(IWRError::SlippageExceeded as u32));
+        }
+        SwapAmount::ExactOut { max_in, .. } if base_amount > max_in => {
+            msg!(
+                "Error: base cost {} is above the maximum of {}",
+                base_amount,
+                max_in
+            );
+            return Err(ProgramError::Custom(IWRError::SlippageExceeded as u32));
+        }
+        _ => {}
+    }
 
-            let token_pool_signer_seeds: &[&[_]] = &[
-                &authority_info.key.to_bytes(),
-                br"token-pool",
-                &[token_pool_bump_seed],
-            ];
+    msg!(
+        "exchanging {} base for {} lamports",
+        base_amount,
+        quote_return
+    );
+
+    msg!("debiting tokens: {}", base_amount);
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            &spl_token::id(),
+            customer_token_associated_info.key,
+            token_pool_info.key,
+            customer_info.key,
+            &[],
+            to_u64(base_amount / base_scale)?,
+        )?,
+        &[
+            token_program_info.clone(),
+            customer_token_associated_info.clone(),
+            token_pool_info.clone(),
+            customer_info.clone(),
+            mint_info.clone(),
+        ],
+        &[mint_signer_seeds, token_pool_signer_seeds],
+    )?;
+
+    msg!(
+        "crediting lamports: {} - fee {} = {}",
+        quote_return,
+        fee_amount,
+        net_quote_return
+    );
+    withdraw_quote(
+        &state,
+        quote_pool_info,
+        customer_quote_info,
+        token_program_info,
+        quote_pool_signer_seeds,
+        net_quote_return,
+    )?;
+
+    // the liquidity providers' part of the fee stays in the pool
+    withdraw_quote(
+        &state,
+        quote_pool_info,
+        beneficiary_info,
+        token_program_info,
+        quote_pool_signer_seeds,
+        sub(fee_amount, lp_fee_amount)?,
+    )?;
+
+    // applying changes to the balance
+    state
+        .curve
+        .apply_sell(&mut state.balance, base_amount, quote_return)?;
+
+    state.balance.quote = add(state.balance.quote, lp_fee_amount)?;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::Sold {
+        state: *state_info.key,
+        customer: *customer_info.key,
+        base_amount,
+        quote_amount: net_quote_return,
+        fee: fee_amount,
+        balance: state.balance.clone(),
+    }
+    .emit()?;
 
-            // ----------------------------------------------------------------------------
-            // native pool account
+    PoolEvent::FeeCollected {
+        state: *state_info.key,
+        beneficiary: *beneficiary_info.key,
+        amount: sub(fee_amount, lp_fee_amount)?,
+        lp_amount: lp_fee_amount,
+    }
+    .emit()?;
 
-            let (native_pool_address, native_pool_bump_seed) =
-                get_native_pool_address_with_seed(authority_info.key);
+    Ok(())
+}
 
-            if native_pool_address != *native_pool_info.key {
-                msg!("Error: native pool address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
-            }
+fn process_add_liquidity(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    quote_amount: u128,
+    max_base_in: u128,
+) -> ProgramResult {
+    let (
+        LiquidityAccounts {
+            state_info,
+            token_pool_info,
+            quote_pool_info,
+            lp_mint_info,
+            provider_info,
+            provider_token_associated_info,
+            provider_lp_associated_info,
+            provider_quote_info,
+            system_program_info,
+            token_program_info,
+            lp_mint_bump_seed,
+            ..
+        },
+        mut state,
+    ) = LiquidityAccounts::load(program_id, accounts)?;
+
+    let lp_mint_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"lp-mint",
+        &[lp_mint_bump_seed],
+    ];
+
+    // ----------------------------------------------------------------------------
+    // processing deposit
+
+    // price curves are a function of the base sold, so extra depth would misprice them
+    if state.curve != CurveType::ConstantProduct {
+        msg!("Error: liquidity can only be added to constant product pools");
+        return Err(ProgramError::Custom(IWRError::UnsupportedCurve as u32));
+    }
 
-            let native_pool_signer_seeds: &[&[_]] = &[
-                &authority_info.key.to_bytes(),
-                br"native-pool",
-                &[native_pool_bump_seed],
-            ];
+    // shares are priced against the real reserves alone
+    if state.has_virtual_reserves() {
+        msg!("Error: liquidity can't be added to pools with virtual reserves");
+        return Err(ProgramError::Custom(
+            IWRError::InvalidVirtualReserves as u32,
+        ));
+    }
 
-            // ----------------------------------------------------------------------------
-            // processing exchange
+    if state.status == PoolStatus::Graduated {
+        msg!("Error: pool has graduated");
+        return Err(ProgramError::Custom(IWRError::PoolGraduated as u32));
+    }
 
-            let mut state: State = State::try_from_slice(*state_info.data.borrow())?;
+    if state.status == PoolStatus::Paused {
+        msg!("Error: trading is paused");
+        return Err(ProgramError::Custom(IWRError::TradingPaused as u32));
+    }
 
-            // making sure that customer account has enough lamports + rent buffer intact
-            let customer_account_minimum_rent =
-                &Rent::get()?.minimum_balance(customer_info.data_len());
+    // graduating needs every LP share redeemed, which any single depositor could hold
+    // off forever, so pools that graduate take no outside liquidity at all
+    if state.graduation_threshold != 0 {
+        msg!("Error: liquidity can't be added to pools that graduate");
+        return Err(ProgramError::Custom(IWRError::GraduatingPool as u32));
+    }
 
-            let quote_threshold = (quote_amount as u64 + customer_account_minimum_rent);
+    // only whole tokens can be transferred, rounding up in the pool's favor
+    let base_scale = state.base_scale();
+    let base_amount = state.balance.calculate_base_for_liquidity(quote_amount)?;
+    let base_amount = mul(div_ceil(base_amount, base_scale)?, base_scale)?;
+    let lp_amount = state
+        .balance
+        .calculate_shares_for_liquidity(quote_amount, state.lp_supply)?;
+
+    if lp_amount == 0 {
+        msg!("Error: deposit is too small to earn any shares");
+        return Err(ProgramError::InvalidArgument);
+    }
 
-            // checking customer lamports
-            if customer_info.lamports() < quote_threshold {
-                msg!("not enough lamports");
-                return Err(ProgramError::Custom(IWRError::NotEnoughLamports as u32));
-            }
+    if base_amount > max_base_in {
+        msg!(
+            "Error: base deposit {} is above the maximum of {}",
+            base_amount,
+            max_base_in
+        );
+        return Err(ProgramError::Custom(IWRError::SlippageExceeded as u32));
+    }
 
-            // calculating base exchange amount
-            let base_return = state.balance.calculate_base_for_quote_amount(quote_amount);
-            let fee_amount = state.balance.calculate_fee_of(quote_amount);
+    msg!(
+        "depositing {} base and {} lamports for {} shares",
+        base_amount,
+        quote_amount,
+        lp_amount
+    );
+
+    transfer_quote(
+        &state,
+        provider_quote_info,
+        provider_info,
+        quote_pool_info,
+        system_program_info,
+        token_program_info,
+        quote_amount,
+    )?;
+
+    invoke(
+        &spl_token::instruction::transfer(
+            &spl_token::id(),
+            provider_token_associated_info.key,
+            token_pool_info.key,
+            provider_info.key,
+            &[],
+            to_u64(base_amount / base_scale)?,
+        )?,
+        &[
+            token_program_info.clone(),
+            provider_token_associated_info.clone(),
+            token_pool_info.clone(),
+            provider_info.clone(),
+        ],
+    )?;
+
+    invoke_signed(
+        &spl_token::instruction::mint_to(
+            &spl_token::id(),
+            lp_mint_info.key,
+            provider_lp_associated_info.key,
+            lp_mint_info.key,
+            &[],
+            to_u64(lp_amount)?,
+        )?,
+        &[
+            lp_mint_info.clone(),
+            provider_lp_associated_info.clone(),
+            token_program_info.clone(),
+        ],
+        &[lp_mint_signer_seeds],
+    )?;
+
+    state
+        .balance
+        .apply_add_liquidity(base_amount, quote_amount)?;
+    state.lp_supply = add(state.lp_supply, lp_amount)?;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::LiquidityAdded {
+        state: *state_info.key,
+        provider: *provider_info.key,
+        base_amount,
+        quote_amount,
+        lp_amount,
+    }
+    .emit()?;
 
-            msg!(
-                "exchanging {} lamports for {} base",
-                quote_amount,
-                base_return
-            );
+    Ok(())
+}
+
+fn process_remove_liquidity(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lp_amount: u128,
+    min_base_out: u128,
+    min_quote_out: u128,
+) -> ProgramResult {
+    let (
+        LiquidityAccounts {
+            state_info,
+            token_pool_info,
+            quote_pool_info,
+            lp_mint_info,
+            provider_info,
+            provider_token_associated_info,
+            provider_lp_associated_info,
+            provider_quote_info,
+            token_program_info,
+            token_pool_bump_seed,
+            quote_pool_bump_seed,
+            quote_pool_seed,
+            ..
+        },
+        mut state,
+    ) = LiquidityAccounts::load(program_id, accounts)?;
+
+    let token_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"token-pool",
+        &[token_pool_bump_seed],
+    ];
+
+    let quote_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        quote_pool_seed,
+        &[quote_pool_bump_seed],
+    ];
+
+    // ----------------------------------------------------------------------------
+    // processing withdrawal
+
+    // the reserves backing the shares left with the graduation
+    if state.status == PoolStatus::Graduated {
+        msg!("Error: pool has graduated");
+        return Err(ProgramError::Custom(IWRError::PoolGraduated as u32));
+    }
+
+    // locked seed shares are never minted, so burning can't drain the pool
+    let (base_amount, quote_amount) = state
+        .balance
+        .calculate_liquidity_for_shares(lp_amount, state.lp_supply)?;
+
+    // only whole tokens can be transferred, rounding down in the pool's favor
+    let base_scale = state.base_scale();
+    let base_amount = base_amount / base_scale * base_scale;
+
+    if base_amount < min_base_out || quote_amount < min_quote_out {
+        msg!(
+            "Error: withdrawal of {} base and {} lamports is below the minimum of {} and {}",
+            base_amount,
+            quote_amount,
+            min_base_out,
+            min_quote_out
+        );
+        return Err(ProgramError::Custom(IWRError::SlippageExceeded as u32));
+    }
+
+    msg!(
+        "withdrawing {} base and {} lamports for {} shares",
+        base_amount,
+        quote_amount,
+        lp_amount
+    );
+
+    invoke(
+        &spl_token::instruction::burn(
+            &spl_token::id(),
+            provider_lp_associated_info.key,
+            lp_mint_info.key,
+            provider_info.key,
+            &[],
+            to_u64(lp_amount)?,
+        )?,
+        &[
+            token_program_info.clone(),
+            provider_lp_associated_info.clone(),
+            lp_mint_info.clone(),
+            provider_info.clone(),
+        ],
+    )?;
+
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            &spl_token::id(),
+            token_pool_info.key,
+            provider_token_associated_info.key,
+            token_pool_info.key,
+            &[],
+            to_u64(base_amount / base_scale)?,
+        )?,
+        &[
+            token_program_info.clone(),
+            token_pool_info.clone(),
+            provider_token_associated_info.clone(),
+        ],
+        &[token_pool_signer_seeds],
+    )?;
+
+    withdraw_quote(
+        &state,
+        quote_pool_info,
+        provider_quote_info,
+        token_program_info,
+        quote_pool_signer_seeds,
+        quote_amount,
+    )?;
+
+    state
+        .balance
+        .apply_remove_liquidity(base_amount, quote_amount)?;
+    state.lp_supply = sub(state.lp_supply, lp_amount)?;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::LiquidityRemoved {
+        state: *state_info.key,
+        provider: *provider_info.key,
+        base_amount,
+        quote_amount,
+        lp_amount,
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+fn process_graduate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let (
+        WithdrawAccounts {
+            state_info,
+            token_pool_info,
+            quote_pool_info,
+            base_destination_info,
+            quote_destination_info,
+            token_program_info,
+            token_pool_bump_seed,
+            quote_pool_bump_seed,
+            quote_pool_seed,
+        },
+        mut state,
+    ) = WithdrawAccounts::load(program_id, accounts)?;
+
+    let token_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"token-pool",
+        &[token_pool_bump_seed],
+    ];
+
+    let quote_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        quote_pool_seed,
+        &[quote_pool_bump_seed],
+    ];
+
+    // ----------------------------------------------------------------------------
+    // processing graduation
+
+    if state.status == PoolStatus::Graduated {
+        msg!("Error: pool has graduated");
+        return Err(ProgramError::Custom(IWRError::PoolGraduated as u32));
+    }
+
+    if state.graduation_threshold == 0 || state.balance.quote < state.graduation_threshold {
+        msg!(
+            "Error: quote balance {} is below the graduation threshold of {}",
+            state.balance.quote,
+            state.graduation_threshold
+        );
+        return Err(ProgramError::Custom(
+            IWRError::GraduationThresholdNotReached as u32,
+        ));
+    }
+
+    // everything held by the pools moves, the native pool only keeps its rent
+    let base_amount = spl_token::state::Account::unpack(&token_pool_info.data.borrow())?.amount;
+    let quote_amount = quote_pool_amount(&state, quote_pool_info)?;
+
+    msg!("moving tokens: {}", base_amount);
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            &spl_token::id(),
+            token_pool_info.key,
+            base_destination_info.key,
+            token_pool_info.key,
+            &[],
+            base_amount,
+        )?,
+        &[
+            token_program_info.clone(),
+            token_pool_info.clone(),
+            base_destination_info.clone(),
+        ],
+        &[token_pool_signer_seeds],
+    )?;
+
+    msg!("moving quote: {}", quote_amount);
+    withdraw_quote(
+        &state,
+        quote_pool_info,
+        quote_destination_info,
+        token_program_info,
+        quote_pool_signer_seeds,
+        quote_amount,
+    )?;
+
+    msg!(
+        "graduated at {} quote, migrated {} tokens and {} quote",
+        state.balance.quote,
+        base_amount,
+        quote_amount
+    );
+
+    state.balance = Balance::default();
+    state.base_dust = 0;
+    state.status = PoolStatus::Graduated;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: state.authority,
+        change: AdminChange::Graduated {
+            base_amount: base_amount as u128,
+            quote_amount,
+        },
+    }
+    .emit()?;
 
-            msg!("debiting lamports: {} + fee {}", quote_amount, fee_amount);
-            /*
-             **customer_info.try_borrow_mut_lamports()? -= quote_amount as u64;
-             **native_pool_info.try_borrow_mut_lamports()? += quote_amount as u64;
-             **beneficiary_info.try_borrow_mut_lamports()? += fee_amount as u64;
-             */
-
-            invoke_signed(
-                &system_instruction::transfer(
-                    customer_info.key,
-                    native_pool_info.key,
-                    quote_amount as u64,
-                ),
-                &[
-                    native_pool_info.clone(),
-                    customer_info.clone(),
-                    system_program_info.clone(),
-                ],
+    Ok(())
+}
+
+fn process_skim(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let (
+        WithdrawAccounts {
+            state_info,
+            token_pool_info,
+            quote_pool_info,
+            base_destination_info,
+            quote_destination_info,
+            token_program_info,
+            token_pool_bump_seed,
+            quote_pool_bump_seed,
+            quote_pool_seed,
+        },
+        mut state,
+    ) = WithdrawAccounts::load(program_id, accounts)?;
+
+    let token_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"token-pool",
+        &[token_pool_bump_seed],
+    ];
+
+    let quote_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        quote_pool_seed,
+        &[quote_pool_bump_seed],
+    ];
+
+    // ----------------------------------------------------------------------------
+    // processing skim
+
+    // the dust is part of the excess too, though only whole tokens of it can leave
+    let base_scale = state.base_scale();
+    let base_held = mul(
+        spl_token::state::Account::unpack(&token_pool_info.data.borrow())?.amount as u128,
+        base_scale,
+    )?;
+    let base_excess = base_held.saturating_sub(state.balance.base);
+    let base_amount = base_excess / base_scale;
+
+    // the native pool keeps its rent
+    let quote_amount =
+        quote_pool_amount(&state, quote_pool_info)?.saturating_sub(state.balance.quote);
+
+    msg!(
+        "skimming {} tokens and {} quote beyond the reserves",
+        base_amount,
+        quote_amount
+    );
+
+    if base_amount > 0 {
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                &spl_token::id(),
+                token_pool_info.key,
+                base_destination_info.key,
+                token_pool_info.key,
                 &[],
-            )?;
+                to_u64(base_amount)?,
+            )?,
+            &[
+                token_program_info.clone(),
+                token_pool_info.clone(),
+                base_destination_info.clone(),
+            ],
+            &[token_pool_signer_seeds],
+        )?;
+
+        state.base_dust = base_excess % base_scale;
+    }
+
+    if quote_amount > 0 {
+        withdraw_quote(
+            &state,
+            quote_pool_info,
+            quote_destination_info,
+            token_program_info,
+            quote_pool_signer_seeds,
+            quote_amount,
+        )?;
+    }
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: state.authority,
+        change: AdminChange::Skimmed {
+            base_amount,
+            quote_amount,
+        },
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+fn process_close_pool(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let (
+        CloseAccounts {
+            state_info,
+            mint_info,
+            token_pool_info,
+            quote_pool_info,
+            lp_mint_info,
+            oracle_info,
+            destination_info,
+            quote_destination_info,
+            token_program_info,
+            token_pool_bump_seed,
+            quote_pool_bump_seed,
+            quote_pool_seed,
+        },
+        state,
+    ) = CloseAccounts::load(program_id, accounts)?;
+
+    let token_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        br"token-pool",
+        &[token_pool_bump_seed],
+    ];
+
+    let quote_pool_signer_seeds: &[&[_]] = &[
+        &state.creator.to_bytes(),
+        &get_pool_index_seed(state.pool_index),
+        quote_pool_seed,
+        &[quote_pool_bump_seed],
+    ];
+
+    // ----------------------------------------------------------------------------
+    // processing close
+
+    if !matches!(state.status, PoolStatus::Paused | PoolStatus::Graduated) {
+        msg!("Error: pool has to be paused or graduated before closing");
+        return Err(ProgramError::Custom(IWRError::PoolNotHalted as u32));
+    }
+
+    // closing would take the liquidity providers' quote along with the rest
+    check_no_liquidity(lp_mint_info)?;
+
+    // tokens nobody bought are burnt, the token pool can only be closed empty
+    let base_amount = spl_token::state::Account::unpack(&token_pool_info.data.borrow())?.amount;
+
+    // until graduation the reserves back every token sold, holders could only sell back
+    if state.status != PoolStatus::Graduated {
+        let supply = spl_token::state::Mint::unpack(&mint_info.data.borrow())?.supply;
+        if supply != base_amount {
+            msg!("Error: {} tokens are circulating", supply - base_amount);
+            return Err(ProgramError::Custom(IWRError::TokensCirculating as u32));
+        }
+    }
 
-            invoke_signed(
-                &system_instruction::transfer(
-                    customer_info.key,
-                    beneficiary_info.key,
-                    fee_amount as u64,
-                ),
-                &[
-                    customer_info.clone(),
-                    beneficiary_info.clone(),
-                    system_program_info.clone(),
-                ],
+    msg!("burning tokens: {}", base_amount);
+    if base_amount > 0 {
+        invoke_signed(
+            &spl_token::instruction::burn(
+                &spl_token::id(),
+                token_pool_info.key,
+                mint_info.key,
+                token_pool_info.key,
                 &[],
-            )?;
+                base_amount,
+            )?,
+            &[
+                token_program_info.clone(),
+                token_pool_info.clone(),
+                mint_info.clone(),
+            ],
+            &[token_pool_signer_seeds],
+        )?;
+    }
 
-            msg!("crediting tokens: {}", base_return);
-            invoke_signed(
-                &spl_token::instruction::transfer(
-                    &spl_token::id(),
-                    token_pool_info.key,
-                    customer_token_associated_info.key,
-                    token_pool_info.key,
-                    &[],
-                    (base_return / BASE_UNIT) as u64,
-                )?,
-                &[
-                    token_program_info.clone(),
-                    token_pool_info.clone(),
-                    customer_token_associated_info.clone(),
-                    token_pool_info.clone(),
-                    mint_info.clone(),
-                ],
-                &[mint_signer_seeds, token_pool_signer_seeds],
+    close_token_account(
+        token_pool_info,
+        destination_info,
+        token_program_info,
+        token_pool_signer_seeds,
+    )?;
+
+    let quote_amount = if state.is_native_quote() {
+        // rent included, the native pool is closed along with the rest
+        let quote_amount = quote_pool_info.lamports() as u128;
+        close_program_account(quote_pool_info, destination_info)?;
+        quote_amount
+    } else {
+        let quote_amount = quote_pool_amount(&state, quote_pool_info)?;
+        if quote_amount > 0 {
+            withdraw_quote(
+                &state,
+                quote_pool_info,
+                quote_destination_info,
+                token_program_info,
+                quote_pool_signer_seeds,
+                quote_amount,
             )?;
+        }
+        close_token_account(
+            quote_pool_info,
+            destination_info,
+            token_program_info,
+            quote_pool_signer_seeds,
+        )?;
+        quote_amount
+    };
+
+    msg!("moved quote: {}", quote_amount);
+
+    // the oracle only exists once someone paid for it
+    if oracle_info.owner == program_id {
+        close_program_account(oracle_info, destination_info)?;
+    }
 
-            // applying changes to the balance
-            state.balance.apply_buy_base_for_quote(quote_amount);
+    close_program_account(state_info, destination_info)?;
 
-            state_info
-                .data
-                .borrow_mut()
-                .copy_from_slice(&state.try_to_vec().unwrap());
-        }
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: state.authority,
+        change: AdminChange::Closed {
+            base_amount: base_amount as u128,
+            quote_amount,
+        },
+    }
+    .emit()?;
 
-        PoolInstruction::Sell(base_amount) => {
-            let authority_info = next_account_info(account_info_iter)?;
-            let state_info = next_account_info(account_info_iter)?;
-            let mint_info = next_account_info(account_info_iter)?;
-            let token_pool_info = next_account_info(account_info_iter)?;
-            let native_pool_info = next_account_info(account_info_iter)?;
-            let beneficiary_info = next_account_info(account_info_iter)?;
-            let customer_info = next_account_info(account_info_iter)?;
-            let customer_token_associated_info = next_account_info(account_info_iter)?;
-            let system_program_info = next_account_info(account_info_iter)?;
-            let token_program_info = next_account_info(account_info_iter)?;
-
-            // ----------------------------------------------------------------------------
-            // state account
-
-            let (state_address, state_bump_seed) = get_state_address_with_seed(authority_info.key);
-
-            if state_address != *state_info.key {
-                msg!("Error: state address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
-            }
+    Ok(())
+}
+
+fn process_set_beneficiary(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    beneficiary: Pubkey,
+) -> ProgramResult {
+    let (AdminAccounts { state_info, .. }, mut state) = AdminAccounts::load(program_id, accounts)?;
+
+    msg!("setting beneficiary: {}", beneficiary);
+    state.beneficiary = beneficiary;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: state.authority,
+        change: AdminChange::Beneficiary(beneficiary),
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+fn process_set_status(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    status: PoolStatus,
+) -> ProgramResult {
+    let (AdminAccounts { state_info, .. }, mut state) = AdminAccounts::load(program_id, accounts)?;
+
+    if state.status == PoolStatus::Graduated {
+        msg!("Error: pool has graduated");
+        return Err(ProgramError::Custom(IWRError::PoolGraduated as u32));
+    }
+
+    msg!("setting status: {:?} -> {:?}", state.status, status);
+    state.status = status;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: state.authority,
+        change: AdminChange::Status(status),
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+fn process_propose_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_authority: Pubkey,
+) -> ProgramResult {
+    let (AdminAccounts { state_info, .. }, mut state) = AdminAccounts::load(program_id, accounts)?;
+
+    msg!("proposing authority: {}", new_authority);
+    state.pending_authority = new_authority;
+
+    state.pack(&mut state_info.data.borrow_mut())?;
+
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: state.authority,
+        change: AdminChange::AuthorityProposed(new_authority),
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+fn process_accept_authority(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // signed by the proposed authority rather than the current one
+    let AdminAccounts {
+        signer_info: new_authority_info,
+        state_info,
+    } = AdminAccounts::load_unchecked(accounts)?;
+
+    let mut state = load_state(program_id, state_info)?;
+
+    if state.pending_authority == Pubkey::default()
+        || state.pending_authority != *new_authority_info.key
+    {
+        msg!("Error: authority was not proposed");
+        return Err(ProgramError::Custom(IWRError::IncorrectAuthority as u32));
+    }
+
+    msg!("accepting authority: {}", new_authority_info.key);
+    state.authority = *new_authority_info.key;
+    state.pending_authority = Pubkey::default();
 
-            let state_signer_seeds: &[&[_]] = &[
-                &authority_info.key.to_bytes(),
-                br"state",
-                &[state_bump_seed],
-            ];
+    state.pack(&mut state_info.data.borrow_mut())?;
 
-            // ----------------------------------------------------------------------------
-            // mint account
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: state.authority,
+        change: AdminChange::AuthorityAccepted,
+    }
+    .emit()?;
 
-            let (mint_address, mint_bump_seed) = get_mint_address_with_seed(authority_info.key);
+    Ok(())
+}
 
-            if mint_address != *mint_info.key {
-                msg!("Error: mint address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
+fn process_migrate_state(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let MigrateAccounts {
+        authority_info,
+        state_info,
+        mint_info,
+        lp_mint_info,
+        system_program_info,
+        token_program_info,
+        rent_sysvar_info,
+    } = MigrateAccounts::load(program_id, accounts)?;
+    let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+    let (state, legacy) = {
+        let data = state_info.data.borrow();
+
+        // the first release had no header, so its size tells it apart
+        match (data.first(), data.len()) {
+            // the mint's address is only checked once the state is decoded, below
+            (_, StateV0::LEN) => {
+                let decimals = spl_token::state::Mint::unpack(&mint_info.data.borrow())?.decimals;
+                (StateV0::try_from_slice(&data)?.into_current(decimals), true)
+            }
+            (Some(&State::CURRENT_VERSION), len) if len >= State::ACCOUNT_LEN => {
+                msg!("Error: state is already migrated");
+                return Err(ProgramError::Custom(IWRError::AlreadyMigrated as u32));
+            }
+            // written before the padding existed, decoded as if it had been there
+            (Some(&State::CURRENT_VERSION), _) => {
+                let mut padded = data.to_vec();
+                padded.resize(State::ACCOUNT_LEN, 0);
+                (State::unpack(&padded)?, false)
             }
+            _ => {
+                msg!("Error: unknown state layout");
+                return Err(ProgramError::Custom(
+                    IWRError::UnsupportedStateVersion as u32,
+                ));
+            }
+        }
+    };
+
+    // older layouts have no header, so only the derived address vouches for them
+    let (state_address, _) = get_state_address_with_seed(&state.creator, state.pool_index);
+
+    if state_address != *state_info.key {
+        msg!("Error: state address derivation mismatch");
+        return Err(ProgramError::Custom(IWRError::IncorrectAddress as u32));
+    }
+
+    if state.authority != *authority_info.key {
+        msg!("Error: incorrect authority");
+        return Err(ProgramError::Custom(IWRError::IncorrectAuthority as u32));
+    }
+
+    let (mint_address, _) = get_mint_address_with_seed(&state.creator, state.pool_index);
+
+    if mint_address != *mint_info.key {
+        msg!("Error: mint address derivation mismatch");
+        return Err(ProgramError::Custom(IWRError::IncorrectAddress as u32));
+    }
+
+    let (lp_mint_address, lp_mint_bump_seed) =
+        get_lp_mint_address_with_seed(&state.creator, state.pool_index);
+
+    if lp_mint_address != *lp_mint_info.key {
+        msg!("Error: lp mint address derivation mismatch");
+        return Err(ProgramError::Custom(IWRError::IncorrectAddress as u32));
+    }
+
+    let required_lamports = rent.minimum_balance(State::ACCOUNT_LEN);
 
-            let mint_signer_seeds: &[&[_]] =
-                &[&authority_info.key.to_bytes(), br"mint", &[mint_bump_seed]];
+    if state_info.lamports() < required_lamports {
+        let lamports = required_lamports - state_info.lamports();
 
-            // ----------------------------------------------------------------------------
-            // token pool account
+        msg!("topping up state rent: {}", lamports);
+        invoke(
+            &system_instruction::transfer(authority_info.key, state_info.key, lamports),
+            &[
+                authority_info.clone(),
+                state_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+    }
+
+    // the first release had no LP tokens, its seed liquidity standing in for the shares
+    if legacy {
+        let lp_mint_signer_seeds: &[&[_]] = &[
+            &state.creator.to_bytes(),
+            &get_pool_index_seed(state.pool_index),
+            br"lp-mint",
+            &[lp_mint_bump_seed],
+        ];
+
+        msg!("creating lp mint");
+        invoke_signed(
+            &system_instruction::create_account(
+                authority_info.key,
+                lp_mint_info.key,
+                1.max(rent.minimum_balance(spl_token::state::Mint::get_packed_len())),
+                spl_token::state::Mint::get_packed_len() as u64,
+                &spl_token::id(),
+            ),
+            &[
+                authority_info.clone(),
+                lp_mint_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[lp_mint_signer_seeds],
+        )?;
+
+        msg!("initializing lp mint");
+        invoke(
+            &spl_token::instruction::initialize_mint(
+                &spl_token::id(),
+                lp_mint_info.key,
+                lp_mint_info.key,
+                None,
+                LP_DECIMALS,
+            )?,
+            &[
+                lp_mint_info.clone(),
+                token_program_info.clone(),
+                rent_sysvar_info.clone(),
+            ],
+        )?;
+    }
+
+    msg!(
+        "migrating state to version {}: {} -> {} bytes",
+        State::CURRENT_VERSION,
+        state_info.data_len(),
+        State::ACCOUNT_LEN
+    );
+    state_info.realloc(State::ACCOUNT_LEN, true)?;
+
+    let mut data = state_info.data.borrow_mut();
+    data.fill(0);
+    state.pack(&mut data)?;
+
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: *authority_info.key,
+        change: AdminChange::StateMigrated,
+    }
+    .emit()?;
 
-            let (token_pool_address, token_pool_bump_seed) =
-                get_token_pool_address_with_seed(authority_info.key);
+    Ok(())
+}
 
-            if token_pool_address != *token_pool_info.key {
-                msg!("Error: token pool address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
+fn process_quote(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    side: Side,
+    amount: u128,
+) -> ProgramResult {
+    let (QuoteAccounts { quote_pool_info }, state) = QuoteAccounts::load(program_id, accounts)?;
+
+    // mirroring the checks and rounding of `Buy` and `Sell`, on a copy of the state
+    let balance = state.effective_balance()?;
+    let base_scale = state.base_scale();
+    let spot_price_before = state.curve.spot_price(&balance)?;
+    let mut state_after = state.clone();
+
+    let (amount_in, amount_out, fee, execution_price) = match side {
+        Side::Buy => {
+            check_buys_allowed(&state)?;
+
+            let base_return = state
+                .curve
+                .calculate_base_for_quote_amount(&balance, amount)?;
+            let base_amount = base_return / base_scale * base_scale;
+
+            if base_amount >= state.balance.base {
+                msg!("not enough tokens");
+                return Err(ProgramError::Custom(IWRError::NotEnoughTokens as u32));
             }
 
-            let token_pool_signer_seeds: &[&[_]] = &[
-                &authority_info.key.to_bytes(),
-                br"token-pool",
-                &[token_pool_bump_seed],
-            ];
+            if base_amount == 0 {
+                msg!("Error: amount buys less than one whole token");
+                return Err(ProgramError::Custom(IWRError::AmountTooSmall as u32));
+            }
+
+            let quote_amount = state
+                .curve
+                .calculate_quote_for_base_out(&balance, base_amount)?
+                .min(amount);
+            let fee = state.fees.calculate_buy_fee(quote_amount)?;
+            let lp_fee = state.fees.calculate_lp_fee_share(fee)?;
+
+            state_after
+                .curve
+                .apply_buy(&mut state_after.balance, base_amount, quote_amount)?;
+            state_after.balance.quote = add(state_after.balance.quote, lp_fee)?;
+
+            let execution_price = mul_div_ceil(quote_amount, BASE_UNIT, base_amount)?;
+
+            (quote_amount, base_amount, fee, execution_price)
+        }
+        Side::Sell => {
+            check_sells_allowed(&state)?;
+
+            let base_amount = amount / base_scale * base_scale;
+
+            if base_amount == 0 {
+                msg!("Error: amount sells less than one whole token");
+                return Err(ProgramError::Custom(IWRError::AmountTooSmall as u32));
+            }
 
-            // ----------------------------------------------------------------------------
-            // native pool account
+            let quote_return = state
+                .curve
+                .calculate_quote_for_base_amount(&balance, base_amount)?;
 
-            let (native_pool_address, native_pool_bump_seed) =
-                get_native_pool_address_with_seed(authority_info.key);
+            if quote_return >= state.balance.quote {
+                msg!("not enough lamports");
+                return Err(ProgramError::Custom(IWRError::NotEnoughLamports as u32));
+            }
 
-            if native_pool_address != *native_pool_info.key {
-                msg!("Error: native pool address derivation mismatch");
-                return Err(ProgramError::InvalidArgument);
+            if quote_pool_amount(&state, quote_pool_info)? < quote_return {
+                msg!("Error: quote pool can't cover the sell");
+                return Err(ProgramError::Custom(IWRError::NotEnoughLamports as u32));
             }
 
-            let native_pool_signer_seeds: &[&[_]] = &[
-                &authority_info.key.to_bytes(),
-                br"native-pool",
-                &[native_pool_bump_seed],
-            ];
+            let fee = state.fees.calculate_sell_fee(quote_return)?;
+            let lp_fee = state.fees.calculate_lp_fee_share(fee)?;
 
-            // ----------------------------------------------------------------------------
-            // processing exchange
+            state_after
+                .curve
+                .apply_sell(&mut state_after.balance, base_amount, quote_return)?;
+            state_after.balance.quote = add(state_after.balance.quote, lp_fee)?;
 
-            let mut state: State = State::try_from_slice(*state_info.data.borrow())?;
+            let execution_price = mul_div_floor(quote_return, BASE_UNIT, base_amount)?;
 
-            let quote_return = state.balance.calculate_quote_for_base_amount(base_amount);
-            let fee_amount = state.balance.calculate_fee_of(quote_return);
-            let net_quote_return = quote_return - fee_amount;
+            (base_amount, sub(quote_return, fee)?, fee, execution_price)
+        }
+    };
+
+    // how much worse than the spot price the trade executes, for either side
+    let price_slip = match side {
+        Side::Buy => execution_price.saturating_sub(spot_price_before),
+        Side::Sell => spot_price_before.saturating_sub(execution_price),
+    };
+
+    let price_impact_bps = match spot_price_before {
+        0 => 0,
+        _ => mul_div_floor(price_slip, FEE_DENOMINATOR, spot_price_before)?,
+    };
+
+    let result = QuoteResult {
+        amount_in,
+        amount_out,
+        fee,
+        price_impact_bps,
+        spot_price_before,
+        spot_price_after: state_after
+            .curve
+            .spot_price(&state_after.effective_balance()?)?,
+    };
+
+    msg!("quote: {:?}", result);
+    set_return_data(&result.try_to_vec()?);
 
-            msg!(
-                "exchanging {} base for {} lamports",
-                base_amount,
-                quote_return
-            );
+    Ok(())
+}
 
-            msg!("debiting tokens: {}", base_amount);
-            invoke_signed(
-                &spl_token::instruction::transfer(
-                    &spl_token::id(),
-                    customer_token_associated_info.key,
-                    token_pool_info.key,
-                    customer_info.key,
-                    &[],
-                    (base_amount / BASE_UNIT) as u64,
-                )?,
-                &[
-                    token_program_info.clone(),
-                    customer_token_associated_info.clone(),
-                    token_pool_info.clone(),
-                    customer_info.clone(),
-                    mint_info.clone(),
-                ],
-                &[mint_signer_seeds, token_pool_signer_seeds],
-            )?;
+fn process_initialize_oracle(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let (
+        OracleAccounts {
+            payer_info,
+            state_info,
+            oracle_info,
+            system_program_info,
+            oracle_bump_seed,
+        },
+        state,
+    ) = OracleAccounts::load(program_id, accounts)?;
+
+    let oracle_signer_seeds: &[&[_]] =
+        &[&state_info.key.to_bytes(), br"oracle", &[oracle_bump_seed]];
+
+    if oracle_info.owner == program_id {
+        msg!("Error: oracle is already initialized");
+        return Err(ProgramError::Custom(IWRError::AlreadyInitialized as u32));
+    }
 
-            msg!(
-                "crediting lamports: {} - fee {} = {}",
-                quote_return,
-                fee_amount,
-                quote_return - fee_amount
-            );
-            **native_pool_info.try_borrow_mut_lamports()? -= quote_return as u64;
-            **customer_info.try_borrow_mut_lamports()? += net_quote_return as u64;
-            **beneficiary_info.try_borrow_mut_lamports()? += fee_amount as u64;
-
-            /*
-            invoke_signed(
-                &system_instruction::transfer(
-                    native_pool_info.key,
-                    customer_info.key,
-                    net_quote_return as u64,
-                ),
-                &[
-                    native_pool_info.clone(),
-                    customer_info.clone(),
-                    system_program_info.clone(),
-                ],
-                &[&native_pool_signer_seeds],
-            )?;
+    msg!("creating oracle account");
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_info.key,
+            oracle_info.key,
+            Rent::get()?.minimum_balance(Oracle::LEN),
+            Oracle::LEN as u64,
+            program_id,
+        ),
+        &[
+            payer_info.clone(),
+            oracle_info.clone(),
+            system_program_info.clone(),
+        ],
+        &[oracle_signer_seeds],
+    )?;
+
+    // starting from the state's accumulator, unless it was never set
+    let mut oracle = Oracle::default();
+
+    if state.oracle.timestamp != 0 {
+        oracle.record(state.oracle);
+    }
 
-            invoke_signed(
-                &system_instruction::transfer(
-                    native_pool_info.key,
-                    beneficiary_info.key,
-                    fee_amount as u64,
-                ),
-                &[
-                    mint_info.clone(),
-                    native_pool_info.clone(),
-                    beneficiary_info.clone(),
-                    system_program_info.clone(),
-                ],
-                &[&native_pool_signer_seeds],
-            )?;
-             */
+    oracle.pack(&mut oracle_info.data.borrow_mut())?;
 
-            // applying changes to the balance
-            state.balance.apply_sell_base(base_amount);
+    PoolEvent::AdminChanged {
+        state: *state_info.key,
+        authority: *payer_info.key,
+        change: AdminChange::OracleInitialized(*oracle_info.key),
+    }
+    .emit()?;
+
+    Ok(())
+}
+
+/// Moves the price accumulator up to the current clock, also recording it in the ring
+/// buffer once the oracle account exists
+fn update_oracle(
+    program_id: &Pubkey,
+    state: &mut State,
+    oracle_info: &AccountInfo,
+) -> ProgramResult {
+    let clock = Clock::get()?;
+    let spot_price = state.curve.spot_price(&state.effective_balance()?)?;
+
+    state.oracle = state
+        .oracle
+        .advance(clock.unix_timestamp, clock.slot, spot_price);
+
+    if oracle_info.owner != program_id {
+        return Ok(());
+    }
+
+    let mut oracle = Oracle::unpack(&oracle_info.data.borrow())?;
+
+    // a second is as fine as the accumulator gets, so later swaps in it aren't recorded
+    if oracle
+        .latest()
+        .map_or(true, |latest| latest.timestamp < state.oracle.timestamp)
+    {
+        oracle.record(state.oracle);
+        oracle.pack(&mut oracle_info.data.borrow_mut())?;
+    }
+
+    Ok(())
+}
+
+fn check_buys_allowed(state: &State) -> ProgramResult {
+    match state.status {
+        PoolStatus::Paused => {
+            msg!("Error: trading is paused");
+            Err(ProgramError::Custom(IWRError::TradingPaused as u32))
+        }
+        PoolStatus::BuysHalted => {
+            msg!("Error: buying is halted");
+            Err(ProgramError::Custom(IWRError::BuysHalted as u32))
+        }
+        PoolStatus::Graduated => {
+            msg!("Error: pool has graduated");
+            Err(ProgramError::Custom(IWRError::PoolGraduated as u32))
+        }
+        _ => check_graduation_threshold(state),
+    }
+}
 
-            state_info
-                .data
-                .borrow_mut()
-                .copy_from_slice(&state.try_to_vec().unwrap());
+fn check_sells_allowed(state: &State) -> ProgramResult {
+    match state.status {
+        PoolStatus::Paused => {
+            msg!("Error: trading is paused");
+            Err(ProgramError::Custom(IWRError::TradingPaused as u32))
+        }
+        PoolStatus::SellsHalted => {
+            msg!("Error: selling is halted");
+            Err(ProgramError::Custom(IWRError::SellsHalted as u32))
+        }
+        PoolStatus::Graduated => {
+            msg!("Error: pool has graduated");
+            Err(ProgramError::Custom(IWRError::PoolGraduated as u32))
         }
+        _ => check_graduation_threshold(state),
+    }
+}
+
+/// Trading freezes once the quote balance reaches the threshold, until the pool graduates
+fn check_graduation_threshold(state: &State) -> ProgramResult {
+    if state.graduation_threshold != 0 && state.balance.quote >= state.graduation_threshold {
+        msg!(
+            "Error: quote balance {} reached the graduation threshold of {}",
+            state.balance.quote,
+            state.graduation_threshold
+        );
+        return Err(ProgramError::Custom(
+            IWRError::GraduationThresholdReached as u32,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Only the seed liquidity, which has no LP tokens, may be left in the pool
+fn check_no_liquidity(lp_mint_info: &AccountInfo) -> ProgramResult {
+    let lp_supply = spl_token::state::Mint::unpack(&lp_mint_info.data.borrow())?.supply;
+
+    if lp_supply != 0 {
+        msg!("Error: {} LP tokens are outstanding", lp_supply);
+        return Err(ProgramError::Custom(IWRError::LiquidityOutstanding as u32));
+    }
+
+    Ok(())
+}
+
+/// Moves quote signed for by `authority_info`, either lamports or tokens of the quote mint
+fn transfer_quote<'a>(
+    state: &State,
+    source_info: &AccountInfo<'a>,
+    authority_info: &AccountInfo<'a>,
+    destination_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    amount: u128,
+) -> ProgramResult {
+    if state.is_native_quote() {
+        invoke(
+            &system_instruction::transfer(source_info.key, destination_info.key, to_u64(amount)?),
+            &[
+                source_info.clone(),
+                destination_info.clone(),
+                system_program_info.clone(),
+            ],
+        )
+    } else {
+        invoke(
+            &spl_token::instruction::transfer(
+                &spl_token::id(),
+                source_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[],
+                to_u64(amount)?,
+            )?,
+            &[
+                token_program_info.clone(),
+                source_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+            ],
+        )
+    }
+}
+
+/// Pays quote out of the quote pool, which the program signs for
+fn withdraw_quote<'a>(
+    state: &State,
+    quote_pool_info: &AccountInfo<'a>,
+    destination_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    quote_pool_signer_seeds: &[&[u8]],
+    amount: u128,
+) -> ProgramResult {
+    if state.is_native_quote() {
+        // the native pool is owned by this program, so lamports are moved directly, never
+        // below its rent-exempt minimum
+        let amount = to_u64(amount)?;
+        let rent = Rent::get()?.minimum_balance(quote_pool_info.data_len());
+        let quote_pool_lamports = quote_pool_info
+            .lamports()
+            .checked_sub(amount)
+            .filter(|lamports| *lamports >= rent)
+            .ok_or(IWRError::NotEnoughLamports)?;
+        let destination_lamports = destination_info
+            .lamports()
+            .checked_add(amount)
+            .ok_or(IWRError::Overflow)?;
+
+        **quote_pool_info.try_borrow_mut_lamports()? = quote_pool_lamports;
+        **destination_info.try_borrow_mut_lamports()? = destination_lamports;
+
+        Ok(())
+    } else {
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                &spl_token::id(),
+                quote_pool_info.key,
+                destination_info.key,
+                quote_pool_info.key,
+                &[],
+                to_u64(amount)?,
+            )?,
+            &[
+                token_program_info.clone(),
+                quote_pool_info.clone(),
+                destination_info.clone(),
+            ],
+            &[quote_pool_signer_seeds],
+        )
     }
+}
+
+/// Closes a token account owned by itself, its rent going to `destination_info`
+fn close_token_account<'a>(
+    account_info: &AccountInfo<'a>,
+    destination_info: &AccountInfo<'a>,
+    token_program_info: &AccountInfo<'a>,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    invoke_signed(
+        &spl_token::instruction::close_account(
+            &spl_token::id(),
+            account_info.key,
+            destination_info.key,
+            account_info.key,
+            &[],
+        )?,
+        &[
+            token_program_info.clone(),
+            account_info.clone(),
+            destination_info.clone(),
+        ],
+        &[signer_seeds],
+    )
+}
+
+/// Zeroes an account owned by this program and moves all of its lamports out, leaving
+/// the runtime to delete it at the end of the transaction
+fn close_program_account(
+    account_info: &AccountInfo,
+    destination_info: &AccountInfo,
+) -> ProgramResult {
+    let destination_lamports = destination_info
+        .lamports()
+        .checked_add(account_info.lamports())
+        .ok_or(IWRError::Overflow)?;
+
+    **destination_info.try_borrow_mut_lamports()? = destination_lamports;
+    **account_info.try_borrow_mut_lamports()? = 0;
+    account_info.data.borrow_mut().fill(0);
 
     Ok(())
 }
+
+/// Narrows an amount for the token and system programs, which count in u64
+fn to_u64(amount: u128) -> Result<u64, IWRError> {
+    u64::try_from(amount).map_err(|_| IWRError::Overflow)
+}
+
+/// Quote actually held by the quote pool, lamports or tokens depending on the quote asset;
+/// the native pool's rent-exempt minimum isn't available
+fn quote_pool_amount(state: &State, quote_pool_info: &AccountInfo) -> Result<u128, ProgramError> {
+    if state.is_native_quote() {
+        let rent = Rent::get()?.minimum_balance(quote_pool_info.data_len());
+        Ok(quote_pool_info.lamports().saturating_sub(rent) as u128)
+    } else {
+        Ok(spl_token::state::Account::unpack(&quote_pool_info.data.borrow())?.amount as u128)
+    }
+}
@@ -1,15 +1,532 @@
 // Based on `record` program state from the solana-program-library
-use crate::balance::Balance;
+use crate::balance::{Balance, CurveType};
+use crate::error::IWRError;
+use crate::math::{add, mul_div_ceil, mul_div_floor, sub};
+use crate::{
+    BASE_DECIMALS, BASE_UNIT, FEE_DENOMINATOR, LEGACY_POOL_INDEX, MAX_FEE_BPS,
+    NATIVE_QUOTE_DECIMALS,
+};
 use {
     borsh::{BorshDeserialize, BorshSchema, BorshSerialize},
-    solana_program::{program_pack::IsInitialized, pubkey::Pubkey},
+    solana_program::{
+        borsh::try_from_slice_unchecked, program_error::ProgramError, program_pack::IsInitialized,
+        pubkey::Pubkey,
+    },
 };
 
 #[derive(Clone, Debug, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
 pub struct State {
+    /// Layout version, leading so it can be read before the rest is decoded
+    pub version: u8,
+    /// Key the pool was created with, seeding every pool address
+    pub creator: Pubkey,
+    pub pool_index: u64,
+    pub authority: Pubkey,
+    /// Authority proposed by the current one, default while there is no proposal;
+    /// kept fixed-size so the state account never has to grow
+    pub pending_authority: Pubkey,
+    pub mint_authority: Pubkey,
+    pub base_pool_authority: Pubkey,
+    pub quote_pool_authority: Pubkey,
+    pub beneficiary: Pubkey,
+    /// Decimals of the pool's own mint, at most `BASE_DECIMALS`
+    pub base_decimals: u8,
+    /// Mint of the quote asset, the native mint for pools quoted in lamports
+    pub quote_mint: Pubkey,
+    pub quote_decimals: u8,
+    /// Real reserves, backed by the token pool and the quote pool
+    pub balance: Balance,
+    /// Base bought below the smallest transferable amount by earlier releases, left in
+    /// the token pool on top of the real reserves
+    pub base_dust: u128,
+    /// Reserves added on top of the real ones when pricing, zero when unused
+    pub virtual_balance: Balance,
+    pub curve: CurveType,
+    pub fees: Fees,
+    pub status: PoolStatus,
+    /// Quote balance at which the pool may graduate, zero for pools that never do
+    pub graduation_threshold: u128,
+    /// Outstanding LP shares, including the seed liquidity locked at initialization
+    pub lp_supply: u128,
+    /// Price accumulator as of the last swap, zeroed for pools that predate it
+    pub oracle: Observation,
+    /// Base counted as `BASE_UNIT` per raw token whatever `base_decimals` says, as the
+    /// first release did
+    pub legacy_scale: bool,
+}
+
+impl State {
+    /// Version of accounts that were never initialized
+    pub const UNINITIALIZED_VERSION: u8 = 0;
+
+    /// Version written by `Initialize`, older ones have to be migrated first
+    pub const CURRENT_VERSION: u8 = 1;
+
+    /// Serialized length with the widest curve, the same as `get_packed_len::<State>()`
+    pub const LEN: usize = 1 // version
+        + 32 + 8 // creator, pool_index
+        + 32 * 6 // authorities and beneficiary
+        + 1 + 32 + 1 // base_decimals, quote_mint, quote_decimals
+        + 32 + 16 + 32 // balance, base_dust, virtual_balance
+        + 1 + 48 // curve
+        + 6 + 1 // fees, status
+        + 16 + 16 // graduation_threshold, lp_supply
+        + 8 + 8 + 16 // oracle
+        + 1; // legacy_scale
+
+    /// Zeroed bytes kept after the state so later fields fit without a realloc, shrinking
+    /// as fields are added so that accounts keep their size
+    pub const RESERVED_LEN: usize = 95;
+
+    /// Size state accounts are allocated with
+    pub const ACCOUNT_LEN: usize = Self::LEN + Self::RESERVED_LEN;
+
+    /// Decodes the state from the start of `data`, ignoring the padding after it
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(try_from_slice_unchecked(data)?)
+    }
+
+    /// Encodes the state into the start of `data`, leaving the padding untouched
+    pub fn pack(&self, mut data: &mut [u8]) -> Result<(), ProgramError> {
+        Ok(self.serialize(&mut data)?)
+    }
+
+    pub fn is_native_quote(&self) -> bool {
+        self.quote_mint == spl_token::native_mint::id()
+    }
+
+    /// Base units making up a single raw token of the mint
+    pub fn base_scale(&self) -> u128 {
+        if self.legacy_scale {
+            BASE_UNIT
+        } else {
+            10u128.pow(BASE_DECIMALS.saturating_sub(self.base_decimals) as u32)
+        }
+    }
+
+    pub fn has_virtual_reserves(&self) -> bool {
+        self.virtual_balance != Balance::default()
+    }
+
+    /// Reserves the curve prices against, real plus virtual
+    pub fn effective_balance(&self) -> Result<Balance, IWRError> {
+        Ok(Balance {
+            base: add(self.balance.base, self.virtual_balance.base)?,
+            quote: add(self.balance.quote, self.virtual_balance.quote)?,
+        })
+    }
+}
+
+impl IsInitialized for State {
+    fn is_initialized(&self) -> bool {
+        self.version != Self::UNINITIALIZED_VERSION
+    }
+}
+
+/// Layout of the first release, at `[authority, "state"]` without a version header or pool
+/// index; only decoded by `MigrateState`
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct StateV0 {
     pub authority: Pubkey,
     pub mint_authority: Pubkey,
     pub base_pool_authority: Pubkey,
     pub quote_pool_authority: Pubkey,
+    /// Base counted as `BASE_UNIT` per raw token, whatever the mint's decimals
     pub balance: Balance,
 }
+
+impl StateV0 {
+    pub const LEN: usize = 32 * 4 + 32;
+
+    /// Flat fee the first release charged on both sides, all of it paid out
+    pub const FEE_BPS: u16 = 30;
+
+    /// Current layout of the same pool, `base_decimals` being those of its mint
+    pub fn into_current(self, base_decimals: u8) -> State {
+        State {
+            version: State::CURRENT_VERSION,
+            creator: self.authority,
+            pool_index: LEGACY_POOL_INDEX,
+            authority: self.authority,
+            pending_authority: Pubkey::default(),
+            mint_authority: self.mint_authority,
+            base_pool_authority: self.base_pool_authority,
+            quote_pool_authority: self.quote_pool_authority,
+            // the first release let every trade pick its beneficiary
+            beneficiary: self.authority,
+            base_decimals,
+            quote_mint: spl_token::native_mint::id(),
+            quote_decimals: NATIVE_QUOTE_DECIMALS,
+            // the quote the pool holds is its seed liquidity
+            lp_supply: self.balance.quote,
+            balance: self.balance,
+            base_dust: 0,
+            virtual_balance: Balance::default(),
+            curve: CurveType::ConstantProduct,
+            fees: Fees {
+                buy_fee_bps: Self::FEE_BPS,
+                sell_fee_bps: Self::FEE_BPS,
+                lp_fee_share_bps: 0,
+            },
+            status: PoolStatus::Active,
+            graduation_threshold: 0,
+            oracle: Observation::default(),
+            // a raw token was a whole one, which keeps the balance as it is
+            legacy_scale: true,
+        }
+    }
+}
+
+/// Time-weighted price accumulator at a point in time
+#[derive(Clone, Copy, Debug, Default, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
+pub struct Observation {
+    pub timestamp: i64,
+    pub slot: u64,
+    /// Spot price in quote units per whole token summed over every second so far; wraps
+    /// on overflow, which the difference between two observations cancels out
+    pub price_cumulative: u128,
+}
+
+impl Observation {
+    pub const LEN: usize = 8 + 8 + 16;
+
+    /// Accumulator moved forward to `timestamp`, `spot_price` having held since the last one
+    pub fn advance(&self, timestamp: i64, slot: u64, spot_price: u128) -> Self {
+        // nothing to weigh before the first observation or when the clock stands still
+        let elapsed = match self.timestamp {
+            0 => 0,
+            _ => timestamp.saturating_sub(self.timestamp).max(0) as u128,
+        };
+
+        Self {
+            timestamp: timestamp.max(self.timestamp),
+            slot,
+            price_cumulative: self
+                .price_cumulative
+                .wrapping_add(spot_price.wrapping_mul(elapsed)),
+        }
+    }
+
+    /// Average spot price since `earlier`, none if no time passed in between
+    pub fn twap(&self, earlier: &Observation) -> Option<u128> {
+        let elapsed = self.timestamp.checked_sub(earlier.timestamp)?;
+
+        if elapsed <= 0 {
+            return None;
+        }
+
+        Some(self.price_cumulative.wrapping_sub(earlier.price_cumulative) / elapsed as u128)
+    }
+}
+
+/// Ring buffer of past observations, kept in its own account next to the state
+#[derive(Clone, Debug, BorshSerialize, BorshDeserialize, PartialEq)]
+pub struct Oracle {
+    /// Index of the latest observation
+    pub head: u16,
+    /// Observations written so far, at most `Oracle::CAPACITY`
+    pub len: u16,
+    pub observations: Vec<Observation>,
+}
+
+impl Oracle {
+    pub const CAPACITY: usize = 64;
+
+    /// Serialized length, the observations being allocated up front
+    pub const LEN: usize = 2 + 2 + 4 + Self::CAPACITY * (8 + 8 + 16);
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(try_from_slice_unchecked(data)?)
+    }
+
+    pub fn pack(&self, mut data: &mut [u8]) -> Result<(), ProgramError> {
+        Ok(self.serialize(&mut data)?)
+    }
+
+    pub fn latest(&self) -> Option<&Observation> {
+        match self.len {
+            0 => None,
+            _ => self.observations.get(self.head as usize),
+        }
+    }
+
+    /// Appends an observation, overwriting the oldest once the buffer is full
+    pub fn record(&mut self, observation: Observation) {
+        if self.len > 0 {
+            self.head = ((self.head as usize + 1) % self.observations.len()) as u16;
+        }
+
+        self.observations[self.head as usize] = observation;
+        self.len = (self.len as usize + 1).min(self.observations.len()) as u16;
+    }
+
+    /// Observations from the oldest to the latest
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Observation> {
+        let start = (self.head as usize + 1 + self.observations.len() - self.len as usize)
+            % self.observations.len();
+
+        (0..self.len as usize)
+            .map(move |i| &self.observations[(start + i) % self.observations.len()])
+    }
+
+    /// Latest observation made at or before `timestamp`
+    pub fn observation_before(&self, timestamp: i64) -> Option<&Observation> {
+        self.iter()
+            .rev()
+            .find(|observation| observation.timestamp <= timestamp)
+    }
+}
+
+impl Default for Oracle {
+    fn default() -> Self {
+        Self {
+            head: 0,
+            len: 0,
+            observations: vec![Observation::default(); Self::CAPACITY],
+        }
+    }
+}
+
+/// Trading mode of the pool, switched by the authority
+#[derive(Clone, Copy, Debug, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
+pub enum PoolStatus {
+    Active,
+    Paused,
+    BuysHalted,
+    SellsHalted,
+    /// Reserves were migrated out by `Graduate`, which is final
+    Graduated,
+}
+
+/// Swap fees charged in quote, in basis points
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, BorshSchema, PartialEq)]
+pub struct Fees {
+    pub buy_fee_bps: u16,
+    pub sell_fee_bps: u16,
+    /// Portion of each fee left in the pool for liquidity providers, in basis points of the fee
+    pub lp_fee_share_bps: u16,
+}
+
+impl Fees {
+    pub fn is_valid(&self) -> bool {
+        self.buy_fee_bps <= MAX_FEE_BPS
+            && self.sell_fee_bps <= MAX_FEE_BPS
+            && self.lp_fee_share_bps as u128 <= FEE_DENOMINATOR
+    }
+
+    pub fn calculate_buy_fee(&self, quote_amount: u128) -> Result<u128, IWRError> {
+        mul_div_floor(quote_amount, self.buy_fee_bps as u128, FEE_DENOMINATOR)
+    }
+
+    pub fn calculate_sell_fee(&self, quote_amount: u128) -> Result<u128, IWRError> {
+        mul_div_floor(quote_amount, self.sell_fee_bps as u128, FEE_DENOMINATOR)
+    }
+
+    /// Part of `fee_amount` kept by liquidity providers, the rest goes to the beneficiary
+    pub fn calculate_lp_fee_share(&self, fee_amount: u128) -> Result<u128, IWRError> {
+        mul_div_floor(fee_amount, self.lp_fee_share_bps as u128, FEE_DENOMINATOR)
+    }
+
+    /// Smallest gross sell return that still leaves `net_quote_amount` after the fee
+    pub fn calculate_quote_before_sell_fee(
+        &self,
+        net_quote_amount: u128,
+    ) -> Result<u128, IWRError> {
+        mul_div_ceil(
+            net_quote_amount,
+            FEE_DENOMINATOR,
+            sub(FEE_DENOMINATOR, self.sell_fee_bps as u128)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::{ConstantSumCurve, ExponentialCurve, LinearCurve};
+
+    #[test]
+    fn test_fees() {
+        let fees = Fees {
+            buy_fee_bps: 30,
+            sell_fee_bps: 125,
+            lp_fee_share_bps: 2500,
+        };
+
+        assert!(fees.is_valid());
+        assert_eq!(fees.calculate_buy_fee(1000000000).unwrap(), 3000000);
+        assert_eq!(fees.calculate_sell_fee(1000000000).unwrap(), 12500000);
+        assert_eq!(fees.calculate_lp_fee_share(12500000).unwrap(), 3125000);
+
+        for net_quote in [0u128, 1, 997, 1000000000] {
+            let gross_quote = fees.calculate_quote_before_sell_fee(net_quote).unwrap();
+            assert!(gross_quote - fees.calculate_sell_fee(gross_quote).unwrap() >= net_quote);
+        }
+
+        assert!(!Fees {
+            buy_fee_bps: MAX_FEE_BPS + 1,
+            ..Fees::default()
+        }
+        .is_valid());
+
+        assert!(!Fees {
+            lp_fee_share_bps: FEE_DENOMINATOR as u16 + 1,
+            ..Fees::default()
+        }
+        .is_valid());
+    }
+
+    fn state_with_curve(curve: CurveType) -> State {
+        State {
+            version: State::CURRENT_VERSION,
+            creator: Pubkey::new_unique(),
+            pool_index: 1,
+            authority: Pubkey::new_unique(),
+            pending_authority: Pubkey::default(),
+            mint_authority: Pubkey::new_unique(),
+            base_pool_authority: Pubkey::new_unique(),
+            quote_pool_authority: Pubkey::new_unique(),
+            beneficiary: Pubkey::new_unique(),
+            base_decimals: 9,
+            quote_mint: spl_token::native_mint::id(),
+            quote_decimals: 9,
+            balance: Balance { base: 1, quote: 2 },
+            base_dust: 3,
+            virtual_balance: Balance::default(),
+            curve,
+            fees: Fees::default(),
+            status: PoolStatus::Active,
+            graduation_threshold: 4,
+            lp_supply: 5,
+            oracle: Observation::default(),
+            legacy_scale: false,
+        }
+    }
+
+    #[test]
+    fn test_state_len() {
+        assert_eq!(State::LEN, solana_program::borsh::get_packed_len::<State>());
+
+        for curve in [
+            CurveType::ConstantProduct,
+            CurveType::Linear(LinearCurve::default()),
+            CurveType::Exponential(ExponentialCurve::default()),
+            CurveType::ConstantSum(ConstantSumCurve::default()),
+        ] {
+            let state = state_with_curve(curve);
+            assert!(state.try_to_vec().unwrap().len() <= State::LEN);
+
+            // padding after the state, whatever the curve, is ignored
+            let mut data = vec![0; State::ACCOUNT_LEN];
+            state.pack(&mut data).unwrap();
+            assert_eq!(State::unpack(&data).unwrap(), state);
+        }
+
+        // but a truncated state isn't
+        let state = state_with_curve(CurveType::Linear(LinearCurve::default()));
+        let data = state.try_to_vec().unwrap();
+        assert!(State::unpack(&data[..data.len() - 1]).is_err());
+        assert!(state.pack(&mut vec![0; data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_oracle() {
+        let first = Observation {
+            timestamp: 1000,
+            slot: 10,
+            price_cumulative: 0,
+        };
+
+        // the price in effect since the last observation is what gets weighed
+        let second = first.advance(1100, 20, 500);
+        assert_eq!(second.price_cumulative, 50000);
+        let third = second.advance(1150, 30, 2000);
+        assert_eq!(third.price_cumulative, 150000);
+        assert_eq!(third.twap(&first), Some(1000));
+        assert_eq!(third.twap(&second), Some(2000));
+        assert_eq!(third.twap(&third), None);
+        assert_eq!(first.twap(&third), None);
+
+        // a clock going backwards never unwinds the accumulator
+        assert_eq!(third.advance(1140, 31, 2000).price_cumulative, 150000);
+        assert_eq!(third.advance(1140, 31, 2000).timestamp, 1150);
+        assert_eq!(
+            Observation::default()
+                .advance(1000, 10, 500)
+                .price_cumulative,
+            0
+        );
+
+        // the accumulator wraps without breaking the average
+        let before_wrap = Observation {
+            timestamp: 1000,
+            slot: 10,
+            price_cumulative: u128::MAX - 99,
+        };
+        assert_eq!(
+            before_wrap.advance(1010, 20, 30).twap(&before_wrap),
+            Some(30)
+        );
+
+        let mut oracle = Oracle::default();
+        assert_eq!(oracle.try_to_vec().unwrap().len(), Oracle::LEN);
+        assert_eq!(oracle.latest(), None);
+
+        for i in 0..Oracle::CAPACITY as i64 + 10 {
+            oracle.record(Observation {
+                timestamp: 1000 + i,
+                ..Observation::default()
+            });
+            assert_eq!(oracle.latest().unwrap().timestamp, 1000 + i);
+        }
+
+        // the oldest observations are the ones overwritten
+        assert_eq!(oracle.len as usize, Oracle::CAPACITY);
+        assert_eq!(oracle.iter().next().unwrap().timestamp, 1010);
+        assert!(oracle
+            .iter()
+            .zip(oracle.iter().skip(1))
+            .all(|(older, newer)| older.timestamp + 1 == newer.timestamp));
+
+        assert_eq!(oracle.observation_before(1009), None);
+        assert_eq!(oracle.observation_before(1040).unwrap().timestamp, 1040);
+        assert_eq!(
+            oracle.observation_before(i64::MAX).unwrap().timestamp,
+            1000 + Oracle::CAPACITY as i64 + 9
+        );
+
+        let mut data = vec![0; Oracle::LEN];
+        oracle.pack(&mut data).unwrap();
+        assert_eq!(Oracle::unpack(&data).unwrap(), oracle);
+    }
+
+    #[test]
+    fn test_fee_overflow() {
+        let fees = Fees {
+            buy_fee_bps: 30,
+            sell_fee_bps: 125,
+            lp_fee_share_bps: 2500,
+        };
+
+        // fees on the largest amounts fit, grossing one up doesn't
+        assert_eq!(
+            fees.calculate_buy_fee(u128::MAX),
+            Ok(u128::MAX / 10000 * 30 + u128::MAX % 10000 * 30 / 10000)
+        );
+        assert_eq!(fees.calculate_lp_fee_share(u128::MAX), Ok(u128::MAX / 4));
+        assert_eq!(
+            fees.calculate_quote_before_sell_fee(u128::MAX),
+            Err(IWRError::Overflow)
+        );
+
+        // unvalidated fees above 100% can't be taken on top
+        assert_eq!(
+            Fees {
+                sell_fee_bps: FEE_DENOMINATOR as u16 + 1,
+                ..Fees::default()
+            }
+            .calculate_quote_before_sell_fee(1),
+            Err(IWRError::Overflow)
+        );
+    }
+}
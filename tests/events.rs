@@ -0,0 +1,285 @@
+// Only the BPF runtime adds logged data to the transaction's log, natively run programs
+// have it printed instead
+#![cfg(feature = "test-bpf")]
+
+use bpf_iwr_ace::balance::{Balance, CurveType};
+use bpf_iwr_ace::event::{parse_events, AdminChange, PoolEvent};
+use bpf_iwr_ace::state::{Fees, State};
+use bpf_iwr_ace::{get_oracle_address, get_pool_addresses, id, instruction};
+use bpf_iwr_ace::{BASE_UNIT, QUOTE_UNIT};
+use solana_program::hash::Hash;
+use solana_program::instruction::Instruction;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+use solana_sdk::signature::Keypair;
+use spl_associated_token_account::get_associated_token_address;
+use spl_token::state::Account as TokenAccount;
+use {
+    solana_program_test::*,
+    solana_sdk::{signature::Signer, transaction::Transaction},
+};
+
+// ----------------------------------------------------------------------------
+// helpers
+
+// pool mints have 9 decimals, so a raw token is worth this many base units
+const DECIMALS: u8 = 9;
+const BASE_SCALE: u128 = BASE_UNIT / 1000000000;
+const TOKEN_SUPPLY: u128 = 1000000 * 1000000000;
+
+fn program_test() -> ProgramTest {
+    // the built program along with the token programs program-test ships
+    let mut program_test = ProgramTest::new("bpf_iwr_ace", id(), None);
+    program_test.prefer_bpf(true);
+    program_test
+}
+
+/// Processes `instructions`, decoding the events the pool logged from the log messages of
+/// simulating them first
+async fn process_with_events(
+    banks_client: &mut BanksClient,
+    instructions: &[Instruction],
+    payer: &Keypair,
+    signers: &[&Keypair],
+    recent_blockhash: Hash,
+) -> Vec<PoolEvent> {
+    let mut tx = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+    tx.sign(&all_signers, recent_blockhash);
+
+    let simulation = banks_client.simulate_transaction(tx.clone()).await.unwrap();
+    simulation.result.unwrap().unwrap();
+    let logs = simulation.simulation_details.unwrap().logs;
+
+    banks_client.process_transaction(tx).await.unwrap();
+
+    parse_events(&id(), &logs).unwrap()
+}
+
+async fn get_state(banks_client: &mut BanksClient, state_address: &Pubkey) -> State {
+    let state_account = banks_client
+        .get_account(*state_address)
+        .await
+        .unwrap()
+        .unwrap();
+    State::unpack(&state_account.data).unwrap()
+}
+
+async fn get_token_balance(banks_client: &mut BanksClient, address: &Pubkey) -> u64 {
+    let account = banks_client.get_account(*address).await.unwrap().unwrap();
+    TokenAccount::unpack_from_slice(&account.data)
+        .unwrap()
+        .amount
+}
+
+#[tokio::test]
+async fn test_pool_events() {
+    let customer = Keypair::new();
+    let beneficiary = Pubkey::new_unique();
+
+    let mut program_test = program_test();
+    program_test.add_account(
+        customer.pubkey(),
+        Account {
+            lamports: (QUOTE_UNIT * 5) as u64,
+            ..Account::default()
+        },
+    );
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+    let fees = Fees {
+        buy_fee_bps: 30,
+        sell_fee_bps: 30,
+        lp_fee_share_bps: 0,
+    };
+
+    // ----------------------------------------------------------------------------
+    // initializing
+
+    let events = process_with_events(
+        &mut banks_client,
+        &[
+            instruction::initialize_pool(
+                &authority.pubkey(),
+                &pool.state,
+                &pool.mint,
+                &pool.token_pool,
+                &pool.native_pool,
+                &pool.lp_mint,
+                &spl_token::native_mint::id(),
+                &authority.pubkey(),
+                0,
+                TOKEN_SUPPLY,
+                DECIMALS,
+                QUOTE_UNIT,
+                fees,
+                CurveType::ConstantProduct,
+                Balance::default(),
+                0,
+                &beneficiary,
+            ),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &customer.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+        ],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await;
+
+    assert_eq!(
+        events,
+        vec![PoolEvent::Initialized {
+            state: pool.state,
+            creator: authority.pubkey(),
+            pool_index: 0,
+            authority: authority.pubkey(),
+            quote_mint: spl_token::native_mint::id(),
+            curve: CurveType::ConstantProduct,
+            balance: Balance {
+                base: TOKEN_SUPPLY * BASE_SCALE,
+                quote: QUOTE_UNIT,
+            },
+        }]
+    );
+
+    // ----------------------------------------------------------------------------
+    // buying, the authority paying for the transaction so the customer's lamports only
+    // move with the trade
+
+    let customer_lamports = banks_client.get_balance(customer.pubkey()).await.unwrap();
+
+    let events = process_with_events(
+        &mut banks_client,
+        &[instruction::buy(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &beneficiary,
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await;
+
+    let customer_tokens = get_token_balance(&mut banks_client, &customer_token_address).await;
+    let state = get_state(&mut banks_client, &pool.state).await;
+    let buy_fee = QUOTE_UNIT * 30 / 10000;
+
+    assert_eq!(
+        events,
+        vec![
+            PoolEvent::Bought {
+                state: pool.state,
+                customer: customer.pubkey(),
+                quote_amount: QUOTE_UNIT,
+                base_amount: customer_tokens as u128 * BASE_SCALE,
+                fee: buy_fee,
+                balance: state.balance.clone(),
+            },
+            PoolEvent::FeeCollected {
+                state: pool.state,
+                beneficiary,
+                amount: buy_fee,
+                lp_amount: 0,
+            },
+        ]
+    );
+    assert_eq!(
+        banks_client.get_balance(customer.pubkey()).await.unwrap(),
+        customer_lamports - (QUOTE_UNIT + buy_fee) as u64
+    );
+
+    // ----------------------------------------------------------------------------
+    // selling half the tokens back
+
+    let customer_lamports = banks_client.get_balance(customer.pubkey()).await.unwrap();
+    let beneficiary_lamports = banks_client.get_balance(beneficiary).await.unwrap();
+    let base_amount = (customer_tokens / 2) as u128 * BASE_SCALE;
+
+    let events = process_with_events(
+        &mut banks_client,
+        &[instruction::sell(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &beneficiary,
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer.pubkey(),
+            base_amount,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await;
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    let quote_amount =
+        banks_client.get_balance(customer.pubkey()).await.unwrap() - customer_lamports;
+    let sell_fee = banks_client.get_balance(beneficiary).await.unwrap() - beneficiary_lamports;
+    assert!(sell_fee > 0);
+
+    assert_eq!(
+        events,
+        vec![
+            PoolEvent::Sold {
+                state: pool.state,
+                customer: customer.pubkey(),
+                base_amount,
+                quote_amount: quote_amount as u128,
+                fee: sell_fee as u128,
+                balance: state.balance,
+            },
+            PoolEvent::FeeCollected {
+                state: pool.state,
+                beneficiary,
+                amount: sell_fee as u128,
+                lp_amount: 0,
+            },
+        ]
+    );
+
+    // ----------------------------------------------------------------------------
+    // initializing the oracle, paid by the customer
+
+    let events = process_with_events(
+        &mut banks_client,
+        &[instruction::initialize_oracle(
+            &customer.pubkey(),
+            &pool.state,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await;
+
+    assert_eq!(
+        events,
+        vec![PoolEvent::AdminChanged {
+            state: pool.state,
+            authority: customer.pubkey(),
+            change: AdminChange::OracleInitialized(get_oracle_address(&pool.state)),
+        }]
+    );
+}
@@ -1,62 +1,322 @@
 // #![cfg(feature = "test-bpf")]
 
 use borsh::BorshDeserialize;
+use bpf_iwr_ace::balance::{Balance, Curve, CurveType, LinearCurve};
+use bpf_iwr_ace::error::IWRError;
+use bpf_iwr_ace::instruction::{QuoteResult, Side};
 use bpf_iwr_ace::processor::process_instruction;
-use bpf_iwr_ace::state::State;
+use bpf_iwr_ace::state::{Fees, Observation, Oracle, PoolStatus, State, StateV0};
 use bpf_iwr_ace::{
-    get_mint_address, get_native_pool_address, get_state_address, get_token_pool_address, id,
-    instruction,
+    get_lp_mint_address, get_mint_address, get_native_pool_address, get_pool_addresses,
+    get_state_address, get_token_pool_address, id, instruction,
 };
-use bpf_iwr_ace::{BASE_UNIT, QUOTE_UNIT};
-use solana_program::msg;
+use bpf_iwr_ace::{BASE_UNIT, LEGACY_POOL_INDEX, MAX_FEE_BPS, QUOTE_UNIT};
+use solana_program::clock::Clock;
+use solana_program::hash::Hash;
+use solana_program::instruction::{AccountMeta, Instruction, InstructionError};
 use solana_program::program_error::ProgramError;
-use solana_program::program_pack::Pack;
+use solana_program::program_pack::{IsInitialized, Pack};
 use solana_program::pubkey::Pubkey;
 use solana_program::rent::Rent;
 use solana_sdk::account::Account;
-use solana_sdk::signature::Keypair;
+use solana_sdk::signature::{keypair_from_seed, Keypair};
+use solana_sdk::transaction::TransactionError;
 use spl_associated_token_account::get_associated_token_address;
 use spl_token::state::Account as TokenAccount;
 use std::borrow::Borrow;
 use {
-    solana_program::system_program,
     solana_program_test::*,
     solana_sdk::{signature::Signer, transaction::Transaction},
 };
 
-#[tokio::test]
-// #[cfg(feature = "test-bpf")]
-async fn test_full_cycle() -> Result<(), ProgramError> {
-    let program_id = id();
+// ----------------------------------------------------------------------------
+// helpers
 
-    let customer = Keypair::new();
-    let beneficiary = Keypair::new();
+// pool mints have 9 decimals, so a raw token is worth this many base units
+const DECIMALS: u8 = 9;
+const BASE_SCALE: u128 = BASE_UNIT / 1000000000;
+const TOKEN_SUPPLY: u128 = 1000000 * 1000000000;
+
+fn program_test() -> ProgramTest {
+    let mut program_test = ProgramTest::new("bpf_iwr_ace", id(), processor!(process_instruction));
+
+    // running the token programs natively as well, so CPIs don't need a BPF toolchain
+    program_test.prefer_bpf(false);
+    program_test.add_program(
+        "spl_token",
+        spl_token::id(),
+        processor!(spl_token::processor::Processor::process),
+    );
+    program_test.add_program(
+        "spl_associated_token_account",
+        spl_associated_token_account::id(),
+        processor!(spl_associated_token_account::processor::process_instruction),
+    );
+
+    program_test
+}
+
+fn default_fees() -> Fees {
+    Fees {
+        buy_fee_bps: 30,
+        sell_fee_bps: 30,
+        lp_fee_share_bps: 0,
+    }
+}
+
+fn add_funded_account(program_test: &mut ProgramTest, keypair: &Keypair, lamports: u64) {
+    program_test.add_account(
+        keypair.pubkey(),
+        Account {
+            lamports,
+            ..Account::default()
+        },
+    );
+}
 
-    let mut program_test =
-        ProgramTest::new("bpf_iwr_ace", program_id, processor!(process_instruction));
+fn add_mint(program_test: &mut ProgramTest, address: &Pubkey, decimals: u8) {
+    let mut data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(
+        spl_token::state::Mint {
+            decimals,
+            is_initialized: true,
+            ..spl_token::state::Mint::default()
+        },
+        &mut data,
+    )
+    .unwrap();
 
     program_test.add_account(
-        customer.pubkey().clone(),
+        *address,
         Account {
-            lamports: (QUOTE_UNIT * 5) as u64,
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: spl_token::id(),
             ..Account::default()
         },
     );
+}
+
+fn add_token_account(
+    program_test: &mut ProgramTest,
+    address: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) {
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount::pack(
+        TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..TokenAccount::default()
+        },
+        &mut data,
+    )
+    .unwrap();
 
     program_test.add_account(
-        beneficiary.pubkey().clone(),
+        *address,
         Account {
-            lamports: 1,
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: spl_token::id(),
             ..Account::default()
         },
     );
+}
+
+async fn process(
+    banks_client: &mut BanksClient,
+    instructions: &[Instruction],
+    payer: &Keypair,
+    signers: &[&Keypair],
+    recent_blockhash: Hash,
+) -> Result<(), TransactionError> {
+    let mut tx = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+    tx.sign(&all_signers, recent_blockhash);
+
+    banks_client
+        .process_transaction(tx)
+        .await
+        .map_err(|e| e.unwrap())
+}
+
+/// Simulates `Quote`, decoding the result it sets as return data
+async fn simulate_quote(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    state_address: &Pubkey,
+    quote_pool_address: &Pubkey,
+    side: Side,
+    amount: u128,
+    recent_blockhash: Hash,
+) -> Result<QuoteResult, TransactionError> {
+    let mut tx = Transaction::new_with_payer(
+        &[instruction::quote(
+            state_address,
+            quote_pool_address,
+            side,
+            amount,
+        )],
+        Some(&payer.pubkey()),
+    );
+    tx.sign(&[payer], recent_blockhash);
+
+    let simulation = banks_client.simulate_transaction(tx).await.unwrap();
+    simulation.result.unwrap()?;
+
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    assert_eq!(return_data.program_id, id());
+
+    let mut data = return_data.data;
+    data.resize(QuoteResult::LEN, 0);
+    Ok(QuoteResult::try_from_slice(&data).unwrap())
+}
+
+async fn get_state(banks_client: &mut BanksClient, state_address: &Pubkey) -> State {
+    let state_account = banks_client
+        .get_account(*state_address)
+        .await
+        .unwrap()
+        .unwrap();
+    State::unpack(&state_account.data).unwrap()
+}
+
+async fn get_token_balance(banks_client: &mut BanksClient, address: &Pubkey) -> u64 {
+    let account = banks_client.get_account(*address).await.unwrap().unwrap();
+    TokenAccount::unpack_from_slice(&account.data)
+        .unwrap()
+        .amount
+}
+
+/// Initializes a pool for the given authority and creates token accounts for the customers
+async fn initialize_pool(
+    banks_client: &mut BanksClient,
+    authority: &Keypair,
+    customers: &[&Keypair],
+    fees: Fees,
+    beneficiary: &Pubkey,
+    recent_blockhash: Hash,
+) -> Result<(), TransactionError> {
+    let mint_address = get_mint_address(&authority.pubkey(), 0);
+
+    let mut instructions = vec![instruction::initialize_pool(
+        &authority.pubkey(),
+        &get_state_address(&authority.pubkey(), 0),
+        &mint_address,
+        &get_token_pool_address(&authority.pubkey(), 0),
+        &get_native_pool_address(&authority.pubkey(), 0),
+        &get_lp_mint_address(&authority.pubkey(), 0),
+        &spl_token::native_mint::id(),
+        &authority.pubkey(),
+        0,
+        TOKEN_SUPPLY,
+        DECIMALS,
+        QUOTE_UNIT,
+        fees,
+        CurveType::ConstantProduct,
+        Balance::default(),
+        0,
+        beneficiary,
+    )];
+
+    for customer in customers {
+        instructions.push(
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &customer.pubkey(),
+                &mint_address,
+                &spl_token::id(),
+            ),
+        );
+    }
+
+    process(
+        banks_client,
+        &instructions,
+        authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+}
+
+fn buy(
+    authority: &Pubkey,
+    beneficiary: &Pubkey,
+    customer: &Pubkey,
+    quote_amount: u128,
+) -> Instruction {
+    let mint_address = get_mint_address(authority, 0);
+
+    instruction::buy(
+        authority,
+        &get_state_address(authority, 0),
+        &mint_address,
+        &get_token_pool_address(authority, 0),
+        &get_native_pool_address(authority, 0),
+        beneficiary,
+        customer,
+        &get_associated_token_address(customer, &mint_address),
+        customer,
+        quote_amount,
+    )
+}
+
+fn sell(
+    authority: &Pubkey,
+    beneficiary: &Pubkey,
+    customer: &Pubkey,
+    base_amount: u128,
+) -> Instruction {
+    let mint_address = get_mint_address(authority, 0);
+
+    instruction::sell(
+        authority,
+        &get_state_address(authority, 0),
+        &mint_address,
+        &get_token_pool_address(authority, 0),
+        &get_native_pool_address(authority, 0),
+        beneficiary,
+        customer,
+        &get_associated_token_address(customer, &mint_address),
+        customer,
+        base_amount,
+    )
+}
+
+/// Same instruction with one of its accounts swapped out
+fn replace_account(
+    mut instruction: Instruction,
+    index: usize,
+    account: AccountMeta,
+) -> Instruction {
+    instruction.accounts[index] = account;
+    instruction
+}
+
+#[tokio::test]
+// #[cfg(feature = "test-bpf")]
+async fn test_full_cycle() -> Result<(), ProgramError> {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &beneficiary, 1);
 
     let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
 
-    let state_address = get_state_address(&authority.pubkey());
-    let mint_address = get_mint_address(&authority.pubkey());
-    let token_pool_address = get_token_pool_address(&authority.pubkey());
-    let native_pool_address = get_native_pool_address(&authority.pubkey());
+    let state_address = get_state_address(&authority.pubkey(), 0);
+    let mint_address = get_mint_address(&authority.pubkey(), 0);
+    let token_pool_address = get_token_pool_address(&authority.pubkey(), 0);
+    let native_pool_address = get_native_pool_address(&authority.pubkey(), 0);
     let customer_associated_token_address =
         get_associated_token_address(&customer.pubkey(), &mint_address);
 
@@ -84,14 +344,24 @@ async fn test_full_cycle() -> Result<(), ProgramError> {
                 &mint_address,
                 &token_pool_address,
                 &native_pool_address,
-                1000000,
-                18,
+                &get_lp_mint_address(&authority.pubkey(), 0),
+                &spl_token::native_mint::id(),
+                &authority.pubkey(),
+                0,
+                TOKEN_SUPPLY,
+                DECIMALS,
                 QUOTE_UNIT,
+                default_fees(),
+                CurveType::ConstantProduct,
+                Balance::default(),
+                0,
+                &beneficiary.pubkey(),
             ),
-            spl_associated_token_account::create_associated_token_account(
+            spl_associated_token_account::instruction::create_associated_token_account(
                 &customer.pubkey(),
                 &customer.pubkey(),
                 &mint_address,
+                &spl_token::id(),
             ),
             instruction::buy(
                 &authority.pubkey(),
@@ -102,6 +372,7 @@ async fn test_full_cycle() -> Result<(), ProgramError> {
                 &beneficiary.pubkey(),
                 &customer.pubkey(),
                 &get_associated_token_address(&customer.pubkey(), &mint_address),
+                &customer.pubkey(),
                 QUOTE_UNIT,
             ),
             instruction::sell(
@@ -113,6 +384,7 @@ async fn test_full_cycle() -> Result<(), ProgramError> {
                 &beneficiary.pubkey(),
                 &customer.pubkey(),
                 &get_associated_token_address(&customer.pubkey(), &mint_address),
+                &customer.pubkey(),
                 BASE_UNIT * 500000,
             ),
         ],
@@ -125,15 +397,24 @@ async fn test_full_cycle() -> Result<(), ProgramError> {
     // ----------------------------------------------------------------------------
     // validation
 
-    let token_pool_account = banks_client.get_account(token_pool_address).await?.unwrap();
+    let token_pool_account = banks_client
+        .get_account(token_pool_address)
+        .await
+        .unwrap()
+        .unwrap();
     let token_pool_state = TokenAccount::unpack_from_slice(token_pool_account.data.borrow())?;
     println!("token pool account = {:#?}\n", token_pool_account);
     println!("token pool token state = {:#?}\n", token_pool_state);
 
-    let customer_account = banks_client.get_account(customer.pubkey()).await?.unwrap();
+    let customer_account = banks_client
+        .get_account(customer.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
     let customer_token_associated_account = banks_client
         .get_account(customer_associated_token_address)
-        .await?
+        .await
+        .unwrap()
         .unwrap();
     let customer_token_state =
         TokenAccount::unpack_from_slice(customer_token_associated_account.data.borrow())?;
@@ -142,7 +423,8 @@ async fn test_full_cycle() -> Result<(), ProgramError> {
 
     let native_pool_account = banks_client
         .get_account(native_pool_address)
-        .await?
+        .await
+        .unwrap()
         .unwrap();
     println!(
         "native pool account after exchange = {:#?}\n",
@@ -151,14 +433,4624 @@ async fn test_full_cycle() -> Result<(), ProgramError> {
 
     let beneficiary_account = banks_client
         .get_account(beneficiary.pubkey())
-        .await?
+        .await
+        .unwrap()
         .unwrap();
     println!(
         "beneficiary account after exchange = {:#?}\n",
         beneficiary_account
     );
 
-    assert_eq!(native_pool_account.lamports, 0);
+    // the sell returns exactly what was bought, leaving the initial quote and rent behind
+    assert_eq!(
+        native_pool_account.lamports,
+        QUOTE_UNIT as u64 + Rent::default().minimum_balance(0)
+    );
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_buy_with_slippage_front_run() {
+    let customer = Keypair::new();
+    let front_runner = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &front_runner, (QUOTE_UNIT * 5) as u64);
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let state_address = get_state_address(&authority.pubkey(), 0);
+    let mint_address = get_mint_address(&authority.pubkey(), 0);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer, &front_runner],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // the customer quotes against the untouched pool
+    let state = get_state(&mut banks_client, &state_address).await;
+    let expected_base = state
+        .balance
+        .calculate_base_for_quote_amount(QUOTE_UNIT)
+        .unwrap();
+
+    let buy_with_slippage = |min_base_out: u128| {
+        instruction::buy_with_slippage(
+            &authority.pubkey(),
+            &state_address,
+            &mint_address,
+            &get_token_pool_address(&authority.pubkey(), 0),
+            &get_native_pool_address(&authority.pubkey(), 0),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            &get_associated_token_address(&customer.pubkey(), &mint_address),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+            min_base_out,
+        )
+    };
+
+    // ----------------------------------------------------------------------------
+    // front-running buy lands first
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &front_runner.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&front_runner],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[buy_with_slippage(expected_base)],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::SlippageExceeded as u32)
+        )
+    );
+
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &mint_address);
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        0
+    );
+
+    // ----------------------------------------------------------------------------
+    // re-quoting against the moved pool goes through
+
+    // only whole tokens are credited, the dust stays in the pool
+    let state = get_state(&mut banks_client, &state_address).await;
+    let expected_base = state
+        .balance
+        .calculate_base_for_quote_amount(QUOTE_UNIT)
+        .unwrap()
+        / BASE_SCALE
+        * BASE_SCALE;
+
+    process(
+        &mut banks_client,
+        &[buy_with_slippage(expected_base)],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        (expected_base / BASE_SCALE) as u64
+    );
+}
+
+#[tokio::test]
+async fn test_sell_with_slippage_front_run() {
+    let customer = Keypair::new();
+    let front_runner = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &front_runner, (QUOTE_UNIT * 5) as u64);
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let state_address = get_state_address(&authority.pubkey(), 0);
+    let mint_address = get_mint_address(&authority.pubkey(), 0);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer, &front_runner],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    process(
+        &mut banks_client,
+        &[
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                QUOTE_UNIT,
+            ),
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &front_runner.pubkey(),
+                QUOTE_UNIT,
+            ),
+        ],
+        &authority,
+        &[&customer, &front_runner],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &mint_address);
+    let base_amount =
+        get_token_balance(&mut banks_client, &customer_token_address).await as u128 * BASE_SCALE;
+
+    // the customer quotes the net return against the current pool
+    let state = get_state(&mut banks_client, &state_address).await;
+    let quote_return = state
+        .balance
+        .calculate_quote_for_base_amount(base_amount)
+        .unwrap();
+    let expected_quote = quote_return - state.fees.calculate_sell_fee(quote_return).unwrap();
+
+    let sell_with_slippage = |min_quote_out: u128| {
+        instruction::sell_with_slippage(
+            &authority.pubkey(),
+            &state_address,
+            &mint_address,
+            &get_token_pool_address(&authority.pubkey(), 0),
+            &get_native_pool_address(&authority.pubkey(), 0),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer.pubkey(),
+            base_amount,
+            min_quote_out,
+        )
+    };
+
+    // ----------------------------------------------------------------------------
+    // front-running sell lands first
+
+    let front_runner_token_address =
+        get_associated_token_address(&front_runner.pubkey(), &mint_address);
+    let front_runner_base_amount = get_token_balance(&mut banks_client, &front_runner_token_address)
+        .await as u128
+        * BASE_SCALE;
+
+    process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &front_runner.pubkey(),
+            front_runner_base_amount,
+        )],
+        &authority,
+        &[&front_runner],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[sell_with_slippage(expected_quote)],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::SlippageExceeded as u32)
+        )
+    );
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await as u128 * BASE_SCALE,
+        base_amount
+    );
+
+    // ----------------------------------------------------------------------------
+    // re-quoting against the moved pool goes through
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    let quote_return = state
+        .balance
+        .calculate_quote_for_base_amount(base_amount)
+        .unwrap();
+    let expected_quote = quote_return - state.fees.calculate_sell_fee(quote_return).unwrap();
+
+    process(
+        &mut banks_client,
+        &[sell_with_slippage(expected_quote)],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_exact_out_swaps() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(
+        &mut program_test,
+        &beneficiary,
+        Rent::default().minimum_balance(0),
+    );
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let state_address = get_state_address(&authority.pubkey(), 0);
+    let mint_address = get_mint_address(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &mint_address);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let buy_exact_out = |base_out: u128, max_quote_in: u128| {
+        instruction::buy_exact_out(
+            &authority.pubkey(),
+            &state_address,
+            &mint_address,
+            &get_token_pool_address(&authority.pubkey(), 0),
+            &get_native_pool_address(&authority.pubkey(), 0),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer.pubkey(),
+            base_out,
+            max_quote_in,
+        )
+    };
+
+    let sell_exact_out = |quote_out: u128, max_base_in: u128| {
+        instruction::sell_exact_out(
+            &authority.pubkey(),
+            &state_address,
+            &mint_address,
+            &get_token_pool_address(&authority.pubkey(), 0),
+            &get_native_pool_address(&authority.pubkey(), 0),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer.pubkey(),
+            quote_out,
+            max_base_in,
+        )
+    };
+
+    // ----------------------------------------------------------------------------
+    // buying an exact amount of tokens
+
+    let base_out = 1000 * BASE_UNIT;
+    let state = get_state(&mut banks_client, &state_address).await;
+    let quote_in = state
+        .balance
+        .calculate_quote_for_base_out(base_out)
+        .unwrap();
+    let total_cost = quote_in + state.fees.calculate_buy_fee(quote_in).unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[buy_exact_out(base_out, total_cost - 1)],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::SlippageExceeded as u32)
+        )
+    );
+
+    let customer_lamports = banks_client.get_balance(customer.pubkey()).await.unwrap();
+
+    process(
+        &mut banks_client,
+        &[buy_exact_out(base_out, total_cost)],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        (base_out / BASE_SCALE) as u64
+    );
+    assert_eq!(
+        banks_client.get_balance(customer.pubkey()).await.unwrap(),
+        customer_lamports - total_cost as u64
+    );
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    assert_eq!(state.balance.quote, QUOTE_UNIT + quote_in);
+
+    // ----------------------------------------------------------------------------
+    // selling for an exact amount of lamports
+
+    let quote_out = QUOTE_UNIT / 10000;
+    let gross_quote = state
+        .fees
+        .calculate_quote_before_sell_fee(quote_out)
+        .unwrap();
+    let base_in = state
+        .balance
+        .calculate_base_for_quote_out(gross_quote)
+        .unwrap();
+    let tokens_in = ((base_in + BASE_SCALE - 1) / BASE_SCALE) as u64;
+
+    let customer_lamports = banks_client.get_balance(customer.pubkey()).await.unwrap();
+
+    process(
+        &mut banks_client,
+        &[sell_exact_out(quote_out, tokens_in as u128 * BASE_SCALE)],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        (base_out / BASE_SCALE) as u64 - tokens_in
+    );
+    assert_eq!(
+        banks_client.get_balance(customer.pubkey()).await.unwrap(),
+        customer_lamports + quote_out as u64
+    );
+}
+
+#[tokio::test]
+async fn test_fee_schedule() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(
+        &mut program_test,
+        &beneficiary,
+        Rent::default().minimum_balance(0),
+    );
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let state_address = get_state_address(&authority.pubkey(), 0);
+    let mint_address = get_mint_address(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &mint_address);
+
+    // ----------------------------------------------------------------------------
+    // rates above the protocol maximum are rejected
+
+    let err = initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[],
+        Fees {
+            buy_fee_bps: 30,
+            sell_fee_bps: MAX_FEE_BPS + 1,
+            lp_fee_share_bps: 0,
+        },
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::FeeTooHigh as u32)
+        )
+    );
+
+    let fees = Fees {
+        buy_fee_bps: 50,
+        sell_fee_bps: 200,
+        lp_fee_share_bps: 0,
+    };
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        fees.clone(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    assert_eq!(state.fees, fees);
+
+    // ----------------------------------------------------------------------------
+    // buying and selling charge their own rates
+
+    let beneficiary_lamports = banks_client
+        .get_balance(beneficiary.pubkey())
+        .await
+        .unwrap();
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let buy_fee = fees.calculate_buy_fee(QUOTE_UNIT).unwrap() as u64;
+    assert_eq!(buy_fee, QUOTE_UNIT as u64 / 200);
+    assert_eq!(
+        banks_client
+            .get_balance(beneficiary.pubkey())
+            .await
+            .unwrap(),
+        beneficiary_lamports + buy_fee
+    );
+
+    let base_amount =
+        get_token_balance(&mut banks_client, &customer_token_address).await as u128 * BASE_SCALE;
+    let state = get_state(&mut banks_client, &state_address).await;
+    let sell_fee = fees
+        .calculate_sell_fee(
+            state
+                .balance
+                .calculate_quote_for_base_amount(base_amount)
+                .unwrap(),
+        )
+        .unwrap() as u64;
+
+    process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            base_amount,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        banks_client
+            .get_balance(beneficiary.pubkey())
+            .await
+            .unwrap(),
+        beneficiary_lamports + buy_fee + sell_fee
+    );
+}
+
+#[tokio::test]
+async fn test_beneficiary_enforcement() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let new_beneficiary = Keypair::new();
+    let impostor = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &impostor, QUOTE_UNIT as u64);
+    for keypair in [&beneficiary, &new_beneficiary] {
+        add_funded_account(
+            &mut program_test,
+            keypair,
+            Rent::default().minimum_balance(0),
+        );
+    }
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let state_address = get_state_address(&authority.pubkey(), 0);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    assert_eq!(state.beneficiary, beneficiary.pubkey());
+
+    // ----------------------------------------------------------------------------
+    // customer routing the fee back to themselves
+
+    let err = process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &customer.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectBeneficiary as u32)
+        )
+    );
+
+    // ----------------------------------------------------------------------------
+    // only the pool authority may rotate the beneficiary
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::set_beneficiary(
+            &impostor.pubkey(),
+            &state_address,
+            &impostor.pubkey(),
+        )],
+        &impostor,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::set_beneficiary(
+            &authority.pubkey(),
+            &state_address,
+            &new_beneficiary.pubkey(),
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    assert_eq!(state.beneficiary, new_beneficiary.pubkey());
+
+    // ----------------------------------------------------------------------------
+    // fees follow the rotation
+
+    let err = process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectBeneficiary as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &new_beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT * 2,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        banks_client
+            .get_balance(new_beneficiary.pubkey())
+            .await
+            .unwrap(),
+        Rent::default().minimum_balance(0)
+            + default_fees().calculate_buy_fee(QUOTE_UNIT * 2).unwrap() as u64
+    );
+}
+
+#[tokio::test]
+async fn test_multiple_pools_per_authority() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(
+        &mut program_test,
+        &beneficiary,
+        Rent::default().minimum_balance(0),
+    );
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let first = get_pool_addresses(&authority.pubkey(), 0);
+    let second = get_pool_addresses(&authority.pubkey(), 1);
+
+    assert_ne!(first, second);
+    assert_eq!(first.state, get_state_address(&authority.pubkey(), 0));
+    assert_eq!(second.mint, get_mint_address(&authority.pubkey(), 1));
+
+    // ----------------------------------------------------------------------------
+    // launching two pools from the same key
+
+    for (pool_index, pool) in [(0, &first), (1, &second)] {
+        process(
+            &mut banks_client,
+            &[
+                instruction::initialize_pool(
+                    &authority.pubkey(),
+                    &pool.state,
+                    &pool.mint,
+                    &pool.token_pool,
+                    &pool.native_pool,
+                    &pool.lp_mint,
+                    &spl_token::native_mint::id(),
+                    &authority.pubkey(),
+                    pool_index,
+                    TOKEN_SUPPLY,
+                    DECIMALS,
+                    QUOTE_UNIT,
+                    default_fees(),
+                    CurveType::ConstantProduct,
+                    Balance::default(),
+                    0,
+                    &beneficiary.pubkey(),
+                ),
+                spl_associated_token_account::instruction::create_associated_token_account(
+                    &authority.pubkey(),
+                    &customer.pubkey(),
+                    &pool.mint,
+                    &spl_token::id(),
+                ),
+            ],
+            &authority,
+            &[],
+            recent_blockhash,
+        )
+        .await
+        .unwrap();
+
+        let state = get_state(&mut banks_client, &pool.state).await;
+        assert_eq!(state.pool_index, pool_index);
+    }
+
+    // ----------------------------------------------------------------------------
+    // accounts of one pool can't be mixed into the other
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::buy(
+            &authority.pubkey(),
+            &first.state,
+            &second.mint,
+            &second.token_pool,
+            &second.native_pool,
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            &get_associated_token_address(&customer.pubkey(), &second.mint),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAddress as u32)
+        )
+    );
+
+    // ----------------------------------------------------------------------------
+    // trading in the second pool leaves the first one untouched
+
+    process(
+        &mut banks_client,
+        &[instruction::buy(
+            &authority.pubkey(),
+            &second.state,
+            &second.mint,
+            &second.token_pool,
+            &second.native_pool,
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            &get_associated_token_address(&customer.pubkey(), &second.mint),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let first_state = get_state(&mut banks_client, &first.state).await;
+    let second_state = get_state(&mut banks_client, &second.state).await;
+
+    assert_eq!(first_state.balance.quote, QUOTE_UNIT);
+    assert_eq!(second_state.balance.quote, QUOTE_UNIT * 2);
+    assert_eq!(
+        get_token_balance(
+            &mut banks_client,
+            &get_associated_token_address(&customer.pubkey(), &first.mint)
+        )
+        .await,
+        0
+    );
+    assert!(
+        get_token_balance(
+            &mut banks_client,
+            &get_associated_token_address(&customer.pubkey(), &second.mint)
+        )
+        .await
+            > 0
+    );
+}
+
+#[tokio::test]
+async fn test_trading_status() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let impostor = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &impostor, QUOTE_UNIT as u64);
+    add_funded_account(
+        &mut program_test,
+        &beneficiary,
+        Rent::default().minimum_balance(0),
+    );
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let state_address = get_state_address(&authority.pubkey(), 0);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    assert_eq!(state.status, PoolStatus::Active);
+
+    // every swap in this test is distinct, so none of them get deduplicated
+    let mut quote_amount = QUOTE_UNIT / 10;
+    let mut base_amount = 1000 * BASE_UNIT;
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // ----------------------------------------------------------------------------
+    // only the authority may change the status
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::pause(&impostor.pubkey(), &state_address)],
+        &impostor,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    // ----------------------------------------------------------------------------
+    // checking both swap directions under each status
+
+    let cases = [
+        (
+            instruction::pause(&authority.pubkey(), &state_address),
+            PoolStatus::Paused,
+            Some(IWRError::TradingPaused),
+            Some(IWRError::TradingPaused),
+        ),
+        (
+            instruction::halt_buys_only(&authority.pubkey(), &state_address),
+            PoolStatus::BuysHalted,
+            Some(IWRError::BuysHalted),
+            None,
+        ),
+        (
+            instruction::halt_sells_only(&authority.pubkey(), &state_address),
+            PoolStatus::SellsHalted,
+            None,
+            Some(IWRError::SellsHalted),
+        ),
+        (
+            instruction::resume(&authority.pubkey(), &state_address),
+            PoolStatus::Active,
+            None,
+            None,
+        ),
+    ];
+
+    for (status_instruction, status, buy_error, sell_error) in cases {
+        process(
+            &mut banks_client,
+            &[status_instruction],
+            &authority,
+            &[],
+            recent_blockhash,
+        )
+        .await
+        .unwrap();
+
+        let state = get_state(&mut banks_client, &state_address).await;
+        assert_eq!(state.status, status);
+
+        let buy_result = process(
+            &mut banks_client,
+            &[buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                quote_amount,
+            )],
+            &authority,
+            &[&customer],
+            recent_blockhash,
+        )
+        .await;
+
+        let sell_result = process(
+            &mut banks_client,
+            &[sell(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                base_amount,
+            )],
+            &authority,
+            &[&customer],
+            recent_blockhash,
+        )
+        .await;
+
+        for (result, expected_error) in [(buy_result, buy_error), (sell_result, sell_error)] {
+            match expected_error {
+                Some(e) => assert_eq!(
+                    result.unwrap_err(),
+                    TransactionError::InstructionError(0, InstructionError::Custom(e as u32))
+                ),
+                None => result.unwrap(),
+            }
+        }
+
+        quote_amount += 1;
+        base_amount += BASE_UNIT;
+    }
+}
+
+#[tokio::test]
+async fn test_authority_transfer() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let new_authority = Keypair::new();
+    let impostor = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    for keypair in [&new_authority, &impostor] {
+        add_funded_account(&mut program_test, keypair, QUOTE_UNIT as u64);
+    }
+    add_funded_account(
+        &mut program_test,
+        &beneficiary,
+        Rent::default().minimum_balance(0),
+    );
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let state_address = get_state_address(&authority.pubkey(), 0);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // ----------------------------------------------------------------------------
+    // proposing a new authority
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::propose_authority(
+            &impostor.pubkey(),
+            &state_address,
+            &impostor.pubkey(),
+        )],
+        &impostor,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::propose_authority(
+            &authority.pubkey(),
+            &state_address,
+            &new_authority.pubkey(),
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    assert_eq!(state.authority, authority.pubkey());
+    assert_eq!(state.pending_authority, new_authority.pubkey());
+
+    // ----------------------------------------------------------------------------
+    // only the proposed key can accept
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::accept_authority(
+            &impostor.pubkey(),
+            &state_address,
+        )],
+        &impostor,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::accept_authority(
+            &new_authority.pubkey(),
+            &state_address,
+        )],
+        &new_authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &state_address).await;
+    assert_eq!(state.creator, authority.pubkey());
+    assert_eq!(state.authority, new_authority.pubkey());
+    assert_eq!(state.pending_authority, Pubkey::default());
+
+    // ----------------------------------------------------------------------------
+    // admin paths follow the new authority, while pool addresses stay put
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::pause(&authority.pubkey(), &state_address)],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::pause(&new_authority.pubkey(), &state_address)],
+        &new_authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::TradingPaused as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::resume(&new_authority.pubkey(), &state_address)],
+        &new_authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // swaps no longer need any authority signature
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT * 2,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_liquidity_provision() {
+    let provider = Keypair::new();
+    let trader = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &provider, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &trader, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(
+        &mut program_test,
+        &beneficiary,
+        Rent::default().minimum_balance(0),
+    );
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let provider_token_address = get_associated_token_address(&provider.pubkey(), &pool.mint);
+    let provider_lp_address = get_associated_token_address(&provider.pubkey(), &pool.lp_mint);
+
+    let fees = Fees {
+        buy_fee_bps: 100,
+        sell_fee_bps: 100,
+        lp_fee_share_bps: 5000,
+    };
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&provider, &trader],
+        fees.clone(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    let initial_lp_supply = state.lp_supply;
+    assert_eq!(initial_lp_supply, QUOTE_UNIT);
+
+    // the provider needs tokens to deposit alongside lamports
+    process(
+        &mut banks_client,
+        &[
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &provider.pubkey(),
+                QUOTE_UNIT,
+            ),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &provider.pubkey(),
+                &provider.pubkey(),
+                &pool.lp_mint,
+                &spl_token::id(),
+            ),
+        ],
+        &provider,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // ----------------------------------------------------------------------------
+    // depositing at the current ratio
+
+    let add_liquidity = |quote_amount, max_base_in| {
+        instruction::add_liquidity(
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &provider.pubkey(),
+            &provider_token_address,
+            &provider_lp_address,
+            &provider.pubkey(),
+            quote_amount,
+            max_base_in,
+        )
+    };
+
+    let quote_deposit = QUOTE_UNIT / 2;
+    let state = get_state(&mut banks_client, &pool.state).await;
+    let base_deposit = state
+        .balance
+        .calculate_base_for_liquidity(quote_deposit)
+        .unwrap();
+    let base_deposit = (base_deposit + BASE_SCALE - 1) / BASE_SCALE * BASE_SCALE;
+    let lp_amount = state
+        .balance
+        .calculate_shares_for_liquidity(quote_deposit, state.lp_supply)
+        .unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[add_liquidity(quote_deposit, base_deposit - BASE_SCALE)],
+        &provider,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::SlippageExceeded as u32)
+        )
+    );
+
+    let provider_tokens = get_token_balance(&mut banks_client, &provider_token_address).await;
+
+    process(
+        &mut banks_client,
+        &[add_liquidity(quote_deposit, base_deposit)],
+        &provider,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &provider_lp_address).await,
+        lp_amount as u64
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &provider_token_address).await,
+        provider_tokens - (base_deposit / BASE_SCALE) as u64
+    );
+
+    let deposited_state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(deposited_state.lp_supply, state.lp_supply + lp_amount);
+    assert_eq!(
+        deposited_state.balance.base,
+        state.balance.base + base_deposit
+    );
+    assert_eq!(
+        deposited_state.balance.quote,
+        state.balance.quote + quote_deposit
+    );
+
+    // ----------------------------------------------------------------------------
+    // half of the fee on a trade stays with the providers
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &trader.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &trader,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let fee = fees.calculate_buy_fee(QUOTE_UNIT).unwrap();
+    let lp_fee = fees.calculate_lp_fee_share(fee).unwrap();
+    assert_eq!(lp_fee * 2, fee);
+
+    let traded_state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        traded_state.balance.quote,
+        deposited_state.balance.quote + QUOTE_UNIT + lp_fee
+    );
+
+    // ----------------------------------------------------------------------------
+    // withdrawing the full position
+
+    let (base_out, quote_out) = traded_state
+        .balance
+        .calculate_liquidity_for_shares(lp_amount, traded_state.lp_supply)
+        .unwrap();
+    let base_out = base_out / BASE_SCALE * BASE_SCALE;
+    assert!(quote_out > quote_deposit);
+
+    let provider_tokens = get_token_balance(&mut banks_client, &provider_token_address).await;
+    let provider_lamports = banks_client.get_balance(provider.pubkey()).await.unwrap();
+
+    process(
+        &mut banks_client,
+        &[instruction::remove_liquidity(
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &provider.pubkey(),
+            &provider_token_address,
+            &provider_lp_address,
+            &provider.pubkey(),
+            lp_amount,
+            base_out,
+            quote_out,
+        )],
+        &authority,
+        &[&provider],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &provider_lp_address).await,
+        0
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &provider_token_address).await,
+        provider_tokens + (base_out / BASE_SCALE) as u64
+    );
+    assert_eq!(
+        banks_client.get_balance(provider.pubkey()).await.unwrap(),
+        provider_lamports + quote_out as u64
+    );
+
+    let withdrawn_state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(withdrawn_state.lp_supply, initial_lp_supply);
+    assert_eq!(
+        withdrawn_state.balance.base,
+        traded_state.balance.base - base_out
+    );
+    assert_eq!(
+        withdrawn_state.balance.quote,
+        traded_state.balance.quote - quote_out
+    );
+}
+
+#[tokio::test]
+async fn test_spl_quoted_pool() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let authority = Keypair::new();
+
+    // a stablecoin-like quote asset with 6 decimals
+    let quote_mint = Pubkey::new_unique();
+    let quote_unit = 1000000u128;
+    let authority_quote_address = get_associated_token_address(&authority.pubkey(), &quote_mint);
+    let customer_quote_address = get_associated_token_address(&customer.pubkey(), &quote_mint);
+    let beneficiary_quote_address =
+        get_associated_token_address(&beneficiary.pubkey(), &quote_mint);
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, QUOTE_UNIT as u64);
+    add_funded_account(&mut program_test, &customer, QUOTE_UNIT as u64);
+    add_mint(&mut program_test, &quote_mint, 6);
+    add_token_account(
+        &mut program_test,
+        &authority_quote_address,
+        &quote_mint,
+        &authority.pubkey(),
+        (quote_unit * 1000) as u64,
+    );
+    add_token_account(
+        &mut program_test,
+        &customer_quote_address,
+        &quote_mint,
+        &customer.pubkey(),
+        (quote_unit * 500) as u64,
+    );
+    add_token_account(
+        &mut program_test,
+        &beneficiary_quote_address,
+        &quote_mint,
+        &beneficiary.pubkey(),
+        0,
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+
+    process(
+        &mut banks_client,
+        &[
+            instruction::initialize_pool(
+                &authority.pubkey(),
+                &pool.state,
+                &pool.mint,
+                &pool.token_pool,
+                &pool.quote_vault,
+                &pool.lp_mint,
+                &quote_mint,
+                &authority_quote_address,
+                0,
+                TOKEN_SUPPLY,
+                DECIMALS,
+                quote_unit * 1000,
+                default_fees(),
+                CurveType::ConstantProduct,
+                Balance::default(),
+                0,
+                &beneficiary_quote_address,
+            ),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &payer.pubkey(),
+                &customer.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+        ],
+        &payer,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert!(!state.is_native_quote());
+    assert_eq!(state.quote_mint, quote_mint);
+    assert_eq!(state.quote_decimals, 6);
+    assert_eq!(state.quote_pool_authority, pool.quote_vault);
+    assert_eq!(
+        get_token_balance(&mut banks_client, &pool.quote_vault).await,
+        (quote_unit * 1000) as u64
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &authority_quote_address).await,
+        0
+    );
+
+    // ----------------------------------------------------------------------------
+    // buying with quote tokens
+
+    let quote_amount = quote_unit * 100;
+    let buy_fee = default_fees().calculate_buy_fee(quote_amount).unwrap();
+    let base_return = state
+        .balance
+        .calculate_base_for_quote_amount(quote_amount)
+        .unwrap();
+
+    process(
+        &mut banks_client,
+        &[instruction::buy(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.quote_vault,
+            &beneficiary_quote_address,
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer_quote_address,
+            quote_amount,
+        )],
+        &payer,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_quote_address).await,
+        (quote_unit * 500 - quote_amount - buy_fee) as u64
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &pool.quote_vault).await,
+        (quote_unit * 1000 + quote_amount) as u64
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &beneficiary_quote_address).await,
+        buy_fee as u64
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        (base_return / BASE_SCALE) as u64
+    );
+
+    // ----------------------------------------------------------------------------
+    // selling back for quote tokens
+
+    let base_amount = base_return / BASE_SCALE * BASE_SCALE;
+    let state = get_state(&mut banks_client, &pool.state).await;
+    let quote_return = state
+        .balance
+        .calculate_quote_for_base_amount(base_amount)
+        .unwrap();
+    let sell_fee = default_fees().calculate_sell_fee(quote_return).unwrap();
+
+    process(
+        &mut banks_client,
+        &[instruction::sell(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.quote_vault,
+            &beneficiary_quote_address,
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer_quote_address,
+            base_amount,
+        )],
+        &payer,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_quote_address).await,
+        (quote_unit * 500 - quote_amount - buy_fee + quote_return - sell_fee) as u64
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &pool.quote_vault).await,
+        (quote_unit * 1000 + quote_amount - quote_return) as u64
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &beneficiary_quote_address).await,
+        (buy_fee + sell_fee) as u64
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        state.balance.quote,
+        quote_unit * 1000 + quote_amount - quote_return
+    );
+}
+
+#[tokio::test]
+async fn test_linear_curve_pool() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let authority = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, _, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+
+    let initialize = |curve: CurveType| {
+        instruction::initialize_pool(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &spl_token::native_mint::id(),
+            &authority.pubkey(),
+            0,
+            TOKEN_SUPPLY,
+            DECIMALS,
+            QUOTE_UNIT,
+            default_fees(),
+            curve,
+            Balance::default(),
+            0,
+            &beneficiary.pubkey(),
+        )
+    };
+
+    // a curve that gives tokens away for free is rejected
+    let err = process(
+        &mut banks_client,
+        &[initialize(CurveType::Linear(LinearCurve::default()))],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::InvalidCurve as u32)
+        )
+    );
+
+    // 0.001 SOL for the first token, rising by 0.00001 SOL per token sold
+    let curve = CurveType::Linear(LinearCurve {
+        initial_price: QUOTE_UNIT / 1000,
+        slope: QUOTE_UNIT / 100000,
+        sold: 0,
+    });
+
+    process(
+        &mut banks_client,
+        &[
+            initialize(curve.clone()),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &customer.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+        ],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.curve, curve);
+
+    // ----------------------------------------------------------------------------
+    // buying walks up the curve
+
+    let quote_amount = QUOTE_UNIT / 10;
+    let base_return = state
+        .curve
+        .calculate_base_for_quote_amount(&state.balance, quote_amount)
+        .unwrap();
+    let first_price = state
+        .curve
+        .calculate_quote_for_base_out(&state.balance, BASE_UNIT)
+        .unwrap();
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            quote_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        (base_return / BASE_SCALE) as u64
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        state.curve,
+        CurveType::Linear(LinearCurve {
+            initial_price: QUOTE_UNIT / 1000,
+            slope: QUOTE_UNIT / 100000,
+            sold: base_return,
+        })
+    );
+    assert_eq!(state.balance.quote, QUOTE_UNIT + quote_amount);
+    assert!(
+        state
+            .curve
+            .calculate_quote_for_base_out(&state.balance, BASE_UNIT)
+            .unwrap()
+            > first_price
+    );
+
+    // ----------------------------------------------------------------------------
+    // selling walks back down
+
+    let base_amount = base_return / BASE_SCALE * BASE_SCALE;
+    let quote_return = state
+        .curve
+        .calculate_quote_for_base_amount(&state.balance, base_amount)
+        .unwrap();
+    assert!(quote_return <= quote_amount);
+
+    process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            base_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        0
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        state.curve,
+        CurveType::Linear(LinearCurve {
+            initial_price: QUOTE_UNIT / 1000,
+            slope: QUOTE_UNIT / 100000,
+            sold: base_return - base_amount,
+        })
+    );
+    assert_eq!(
+        state.balance.quote,
+        QUOTE_UNIT + quote_amount - quote_return
+    );
+
+    // ----------------------------------------------------------------------------
+    // liquidity only makes sense for constant product pools
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::add_liquidity(
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &customer.pubkey(),
+            &customer_token_address,
+            &get_associated_token_address(&customer.pubkey(), &pool.lp_mint),
+            &customer.pubkey(),
+            QUOTE_UNIT / 10,
+            BASE_UNIT * 100,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::UnsupportedCurve as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_virtual_reserves() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let authority = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, _, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+
+    let initialize = |virtual_balance: Balance| {
+        instruction::initialize_pool(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &spl_token::native_mint::id(),
+            &authority.pubkey(),
+            0,
+            TOKEN_SUPPLY,
+            DECIMALS,
+            0,
+            default_fees(),
+            CurveType::ConstantProduct,
+            virtual_balance,
+            0,
+            &beneficiary.pubkey(),
+        )
+    };
+
+    // without a deposit or virtual quote there is no starting price
+    let err = process(
+        &mut banks_client,
+        &[initialize(Balance::default())],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::InvalidVirtualReserves as u32)
+        )
+    );
+
+    let virtual_balance = Balance {
+        base: 100000 * BASE_UNIT,
+        quote: 30 * QUOTE_UNIT,
+    };
+
+    process(
+        &mut banks_client,
+        &[
+            initialize(virtual_balance.clone()),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &customer.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+        ],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // nothing but the rent was deposited up front
+    let native_pool_rent = Rent::default().minimum_balance(0);
+    assert_eq!(
+        banks_client.get_balance(pool.native_pool).await.unwrap(),
+        native_pool_rent
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.balance.quote, 0);
+    assert_eq!(state.virtual_balance, virtual_balance);
+    assert_eq!(
+        state.effective_balance().unwrap(),
+        Balance {
+            base: 1100000 * BASE_UNIT,
+            quote: 30 * QUOTE_UNIT,
+        }
+    );
+
+    // ----------------------------------------------------------------------------
+    // buying prices against the virtual reserves
+
+    let quote_amount = QUOTE_UNIT;
+    let base_return = state
+        .effective_balance()
+        .unwrap()
+        .calculate_base_for_quote_amount(quote_amount)
+        .unwrap();
+    assert!(
+        base_return
+            < state
+                .balance
+                .calculate_base_for_quote_amount(quote_amount)
+                .unwrap()
+    );
+
+    // only the whole tokens are paid for
+    let base_amount = base_return / BASE_SCALE * BASE_SCALE;
+    let quote_paid = state
+        .effective_balance()
+        .unwrap()
+        .calculate_quote_for_base_out(base_amount)
+        .unwrap();
+    assert!(quote_paid <= quote_amount);
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            quote_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        (base_amount / BASE_SCALE) as u64
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        state.balance,
+        Balance {
+            base: 1000000 * BASE_UNIT - base_amount,
+            quote: quote_paid,
+        }
+    );
+    assert_eq!(state.virtual_balance, virtual_balance);
+
+    // ----------------------------------------------------------------------------
+    // selling everything back is covered by the real lamports
+
+    let quote_return = state
+        .effective_balance()
+        .unwrap()
+        .calculate_quote_for_base_amount(base_amount)
+        .unwrap();
+    assert!(quote_return <= quote_paid);
+
+    process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            base_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.balance.quote, quote_paid - quote_return);
+    assert_eq!(
+        banks_client.get_balance(pool.native_pool).await.unwrap(),
+        native_pool_rent + (quote_paid - quote_return) as u64
+    );
+
+    // ----------------------------------------------------------------------------
+    // liquidity shares can't be priced against virtual reserves
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::add_liquidity(
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &customer.pubkey(),
+            &customer_token_address,
+            &get_associated_token_address(&customer.pubkey(), &pool.lp_mint),
+            &customer.pubkey(),
+            QUOTE_UNIT / 10,
+            BASE_UNIT * 100,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::InvalidVirtualReserves as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_graduation() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let authority = Keypair::new();
+    let quote_destination = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, _, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+    let base_destination_address = get_associated_token_address(&authority.pubkey(), &pool.mint);
+
+    process(
+        &mut banks_client,
+        &[
+            instruction::initialize_pool(
+                &authority.pubkey(),
+                &pool.state,
+                &pool.mint,
+                &pool.token_pool,
+                &pool.native_pool,
+                &pool.lp_mint,
+                &spl_token::native_mint::id(),
+                &authority.pubkey(),
+                0,
+                TOKEN_SUPPLY,
+                DECIMALS,
+                QUOTE_UNIT,
+                default_fees(),
+                CurveType::ConstantProduct,
+                Balance::default(),
+                QUOTE_UNIT * 2,
+                &beneficiary.pubkey(),
+            ),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &customer.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &authority.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+        ],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let customer_lp_address = get_associated_token_address(&customer.pubkey(), &pool.lp_mint);
+
+    let graduate = |authority: &Pubkey| {
+        instruction::graduate(
+            authority,
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &base_destination_address,
+            &quote_destination.pubkey(),
+        )
+    };
+    let add_liquidity = |quote_amount| {
+        instruction::add_liquidity(
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer_lp_address,
+            &customer.pubkey(),
+            quote_amount,
+            BASE_UNIT * 1000000,
+        )
+    };
+
+    // the curve hasn't filled yet, paid by the customer to keep the transaction unique
+    let err = process(
+        &mut banks_client,
+        &[graduate(&authority.pubkey())],
+        &customer,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::GraduationThresholdNotReached as u32)
+        )
+    );
+
+    // ----------------------------------------------------------------------------
+    // filling the curve, with the customer trying to provide liquidity along the way
+
+    let graduating_pool = TransactionError::InstructionError(
+        0,
+        InstructionError::Custom(IWRError::GraduatingPool as u32),
+    );
+
+    process(
+        &mut banks_client,
+        &[
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                QUOTE_UNIT / 2,
+            ),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &customer.pubkey(),
+                &customer.pubkey(),
+                &pool.lp_mint,
+                &spl_token::id(),
+            ),
+        ],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // a single LP share could hold graduation off, so none are handed out
+    let err = process(
+        &mut banks_client,
+        &[add_liquidity(QUOTE_UNIT / 10)],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, graduating_pool);
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_lp_address).await,
+        0
+    );
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert!(state.balance.quote >= state.graduation_threshold);
+
+    // past the threshold the pool waits for graduation
+    let threshold_reached = TransactionError::InstructionError(
+        0,
+        InstructionError::Custom(IWRError::GraduationThresholdReached as u32),
+    );
+
+    for instruction in [
+        buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT / 20,
+        ),
+        sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            BASE_UNIT * 2,
+        ),
+    ] {
+        let err = process(
+            &mut banks_client,
+            &[instruction],
+            &customer,
+            &[],
+            recent_blockhash,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, threshold_reached);
+    }
+
+    let err = process(
+        &mut banks_client,
+        &[add_liquidity(QUOTE_UNIT / 20)],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, graduating_pool);
+
+    let customer_tokens = get_token_balance(&mut banks_client, &customer_token_address).await;
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert!(state.balance.quote >= state.graduation_threshold);
+
+    // only the authority picks where the reserves go
+    let err = process(
+        &mut banks_client,
+        &[graduate(&customer.pubkey())],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[graduate(&authority.pubkey())],
+        &beneficiary,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &base_destination_address).await,
+        TOKEN_SUPPLY as u64 - customer_tokens
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &pool.token_pool).await,
+        0
+    );
+    assert_eq!(
+        banks_client
+            .get_balance(quote_destination.pubkey())
+            .await
+            .unwrap(),
+        state.balance.quote as u64
+    );
+    assert_eq!(
+        banks_client.get_balance(pool.native_pool).await.unwrap(),
+        Rent::default().minimum_balance(0)
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.status, PoolStatus::Graduated);
+    assert_eq!(state.balance.base, 0);
+    assert_eq!(state.balance.quote, 0);
+
+    // ----------------------------------------------------------------------------
+    // graduation is final
+
+    let pool_graduated = TransactionError::InstructionError(
+        0,
+        InstructionError::Custom(IWRError::PoolGraduated as u32),
+    );
+
+    let err = process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT / 10,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, pool_graduated);
+
+    let err = process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            BASE_UNIT,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, pool_graduated);
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::resume(&authority.pubkey(), &pool.state)],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, pool_graduated);
+
+    let err = process(
+        &mut banks_client,
+        &[graduate(&authority.pubkey())],
+        &quote_destination,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, pool_graduated);
+}
+
+#[tokio::test]
+async fn test_arithmetic_overflow() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let authority = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, _, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+
+    let overflow =
+        TransactionError::InstructionError(0, InstructionError::Custom(IWRError::Overflow as u32));
+
+    // a supply the token program can't mint
+    let err = process(
+        &mut banks_client,
+        &[instruction::initialize_pool(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &spl_token::native_mint::id(),
+            &authority.pubkey(),
+            0,
+            u64::MAX as u128 + 1,
+            DECIMALS,
+            QUOTE_UNIT,
+            default_fees(),
+            CurveType::ConstantProduct,
+            Balance::default(),
+            0,
+            &beneficiary.pubkey(),
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, overflow);
+
+    // a supply whose base units don't fit in u128, with a whole token per raw one
+    let err = process(
+        &mut banks_client,
+        &[instruction::initialize_pool(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &spl_token::native_mint::id(),
+            &authority.pubkey(),
+            0,
+            u128::MAX / BASE_UNIT + 1,
+            0,
+            QUOTE_UNIT,
+            default_fees(),
+            CurveType::ConstantProduct,
+            Balance::default(),
+            0,
+            &beneficiary.pubkey(),
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, overflow);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // swap amounts large enough to overflow the pricing
+    for instruction in [
+        buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            u128::MAX,
+        ),
+        sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            u128::MAX,
+        ),
+        instruction::sell_exact_out(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            &get_associated_token_address(&customer.pubkey(), &pool.mint),
+            &customer.pubkey(),
+            u128::MAX,
+            u128::MAX,
+        ),
+    ] {
+        let err = process(
+            &mut banks_client,
+            &[instruction],
+            &customer,
+            &[],
+            recent_blockhash,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, overflow);
+    }
+
+    // nothing moved
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.balance.base, 1000000 * BASE_UNIT);
+    assert_eq!(state.balance.quote, QUOTE_UNIT);
+}
+
+#[tokio::test]
+async fn test_buy_lamports_include_fee() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let quote_amount = QUOTE_UNIT / 10;
+    let fee = default_fees().calculate_buy_fee(quote_amount).unwrap() as u64;
+    let customer_rent = Rent::default().minimum_balance(0);
+
+    // the amount and the rent are there, one lamport of the fee isn't
+    let mut program_test = program_test();
+    add_funded_account(
+        &mut program_test,
+        &customer,
+        quote_amount as u64 + fee + customer_rent - 1,
+    );
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            quote_amount,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::NotEnoughLamports as u32)
+        )
+    );
+
+    // topped up, the buy leaves the customer exactly their rent
+    process(
+        &mut banks_client,
+        &[
+            solana_sdk::system_instruction::transfer(&authority.pubkey(), &customer.pubkey(), 1),
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                quote_amount,
+            ),
+        ],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        banks_client.get_balance(customer.pubkey()).await.unwrap(),
+        customer_rent
+    );
+}
+
+#[tokio::test]
+async fn test_mint_decimals() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let authority = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, _, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+
+    let initialize = |decimals: u8| {
+        instruction::initialize_pool(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &spl_token::native_mint::id(),
+            &authority.pubkey(),
+            0,
+            1000000 * 1000000,
+            decimals,
+            QUOTE_UNIT,
+            default_fees(),
+            CurveType::ConstantProduct,
+            Balance::default(),
+            0,
+            &beneficiary.pubkey(),
+        )
+    };
+
+    // raw tokens can't be finer than the base precision
+    let err = process(
+        &mut banks_client,
+        &[initialize(19)],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::InvalidDecimals as u32)
+        )
+    );
+
+    // a million tokens with 6 decimals
+    process(
+        &mut banks_client,
+        &[
+            initialize(6),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &customer.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+        ],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let base_scale = BASE_UNIT / 1000000;
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.base_decimals, 6);
+    assert_eq!(state.base_scale(), base_scale);
+    assert_eq!(state.balance.base, 1000000 * BASE_UNIT);
+    assert_eq!(state.base_dust, 0);
+
+    // ----------------------------------------------------------------------------
+    // buying credits whole raw tokens and charges for those alone
+
+    let quote_amount = QUOTE_UNIT / 3;
+    let base_return = state
+        .balance
+        .calculate_base_for_quote_amount(quote_amount)
+        .unwrap();
+    assert_ne!(base_return % base_scale, 0);
+
+    let base_amount = base_return / base_scale * base_scale;
+    let quote_paid = state
+        .balance
+        .calculate_quote_for_base_out(base_amount)
+        .unwrap();
+    assert!(quote_paid <= quote_amount);
+    let quote_before = state.balance.quote;
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            quote_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let customer_tokens = get_token_balance(&mut banks_client, &customer_token_address).await;
+    assert_eq!(customer_tokens as u128, base_return / base_scale);
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.balance.base, 1000000 * BASE_UNIT - base_amount);
+    assert_eq!(state.balance.quote, quote_before + quote_paid);
+    assert_eq!(state.base_dust, 0);
+
+    // the token pool holds exactly the reserves
+    assert_eq!(
+        get_token_balance(&mut banks_client, &pool.token_pool).await as u128 * base_scale,
+        state.balance.base
+    );
+
+    // ----------------------------------------------------------------------------
+    // selling a fraction of a raw token only sells the whole ones
+
+    let quote_return = state
+        .balance
+        .calculate_quote_for_base_amount(customer_tokens as u128 * base_scale)
+        .unwrap();
+
+    process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            base_return,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        0
+    );
+
+    let sold_state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        sold_state.balance.base,
+        state.balance.base + customer_tokens as u128 * base_scale
+    );
+    assert_eq!(sold_state.balance.quote, state.balance.quote - quote_return);
+    assert_eq!(sold_state.base_dust, state.base_dust);
+}
+
+#[tokio::test]
+async fn test_buy_below_one_token() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let authority = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 10) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, _, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+
+    // a million indivisible tokens for a thousand lamports each
+    process(
+        &mut banks_client,
+        &[
+            instruction::initialize_pool(
+                &authority.pubkey(),
+                &pool.state,
+                &pool.mint,
+                &pool.token_pool,
+                &pool.native_pool,
+                &pool.lp_mint,
+                &spl_token::native_mint::id(),
+                &authority.pubkey(),
+                0,
+                1000000,
+                0,
+                QUOTE_UNIT,
+                default_fees(),
+                CurveType::ConstantProduct,
+                Balance::default(),
+                0,
+                &beneficiary.pubkey(),
+            ),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &authority.pubkey(),
+                &customer.pubkey(),
+                &pool.mint,
+                &spl_token::id(),
+            ),
+        ],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let base_scale = BASE_UNIT;
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.base_scale(), base_scale);
+
+    // ----------------------------------------------------------------------------
+    // buying less than a token charges nothing
+
+    let quote_amount = 500;
+    assert!(
+        state
+            .balance
+            .calculate_base_for_quote_amount(quote_amount)
+            .unwrap()
+            < base_scale
+    );
+
+    let amount_too_small = TransactionError::InstructionError(
+        0,
+        InstructionError::Custom(IWRError::AmountTooSmall as u32),
+    );
+
+    let err = simulate_quote(
+        &mut banks_client,
+        &customer,
+        &pool.state,
+        &pool.native_pool,
+        Side::Buy,
+        quote_amount,
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, amount_too_small);
+
+    let err = process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            quote_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, amount_too_small);
+    assert_eq!(get_state(&mut banks_client, &pool.state).await, state);
+
+    // ----------------------------------------------------------------------------
+    // buying a token and a half charges for the whole one only
+
+    let quote_amount = 1500;
+    let quote_paid = state
+        .balance
+        .calculate_quote_for_base_out(base_scale)
+        .unwrap();
+    assert!(quote_paid < quote_amount);
+
+    let quote = simulate_quote(
+        &mut banks_client,
+        &customer,
+        &pool.state,
+        &pool.native_pool,
+        Side::Buy,
+        quote_amount,
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(quote.amount_in, quote_paid);
+    assert_eq!(quote.amount_out, base_scale);
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            quote_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        1
+    );
+
+    let bought_state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(bought_state.balance.base, state.balance.base - base_scale);
+    assert_eq!(
+        bought_state.balance.quote,
+        state.balance.quote
+            + quote_paid
+            + state
+                .fees
+                .calculate_lp_fee_share(state.fees.calculate_buy_fee(quote_paid).unwrap())
+                .unwrap()
+    );
+    assert_eq!(bought_state.base_dust, 0);
+}
+
+#[tokio::test]
+async fn test_account_validation() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let buy = buy(
+        &authority.pubkey(),
+        &beneficiary.pubkey(),
+        &customer.pubkey(),
+        QUOTE_UNIT,
+    );
+    let sell = sell(
+        &authority.pubkey(),
+        &beneficiary.pubkey(),
+        &customer.pubkey(),
+        BASE_UNIT,
+    );
+    let add_liquidity = instruction::add_liquidity(
+        &pool.state,
+        &pool.token_pool,
+        &pool.native_pool,
+        &pool.lp_mint,
+        &customer.pubkey(),
+        &customer_token_address,
+        &get_associated_token_address(&customer.pubkey(), &pool.lp_mint),
+        &customer.pubkey(),
+        QUOTE_UNIT / 10,
+        BASE_UNIT * 100,
+    );
+    let initialize = instruction::initialize_pool(
+        &authority.pubkey(),
+        &get_state_address(&authority.pubkey(), 1),
+        &get_mint_address(&authority.pubkey(), 1),
+        &get_token_pool_address(&authority.pubkey(), 1),
+        &get_native_pool_address(&authority.pubkey(), 1),
+        &get_lp_mint_address(&authority.pubkey(), 1),
+        &spl_token::native_mint::id(),
+        &authority.pubkey(),
+        1,
+        TOKEN_SUPPLY,
+        DECIMALS,
+        QUOTE_UNIT,
+        default_fees(),
+        CurveType::ConstantProduct,
+        Balance::default(),
+        0,
+        &beneficiary.pubkey(),
+    );
+
+    for (instruction, payer, signers, error) in [
+        // a state account the program doesn't own
+        (
+            replace_account(buy.clone(), 1, AccountMeta::new(customer.pubkey(), false)),
+            &authority,
+            vec![&customer],
+            IWRError::IncorrectOwner,
+        ),
+        // a program posing as the token program
+        (
+            replace_account(
+                buy.clone(),
+                10,
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            ),
+            &authority,
+            vec![&customer],
+            IWRError::IncorrectProgram,
+        ),
+        (
+            replace_account(
+                initialize,
+                9,
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            ),
+            &authority,
+            vec![],
+            IWRError::IncorrectProgram,
+        ),
+        // token accounts other than the customer's associated ones
+        (
+            replace_account(
+                buy.clone(),
+                7,
+                AccountMeta::new(
+                    get_associated_token_address(&authority.pubkey(), &pool.mint),
+                    false,
+                ),
+            ),
+            &authority,
+            vec![&customer],
+            IWRError::IncorrectAddress,
+        ),
+        (
+            replace_account(
+                add_liquidity,
+                6,
+                AccountMeta::new(customer_token_address, false),
+            ),
+            &customer,
+            vec![],
+            IWRError::IncorrectAddress,
+        ),
+        // selling someone else's tokens without their signature
+        (
+            replace_account(sell, 6, AccountMeta::new(customer.pubkey(), false)),
+            &authority,
+            vec![],
+            IWRError::MissingSignature,
+        ),
+        (
+            replace_account(
+                instruction::set_beneficiary(&authority.pubkey(), &pool.state, &customer.pubkey()),
+                0,
+                AccountMeta::new_readonly(authority.pubkey(), false),
+            ),
+            &customer,
+            vec![],
+            IWRError::MissingSignature,
+        ),
+        // a state account the swap can't write to
+        (
+            replace_account(buy, 1, AccountMeta::new_readonly(pool.state, false)),
+            &authority,
+            vec![&customer],
+            IWRError::AccountNotWritable,
+        ),
+    ] {
+        let err = process(
+            &mut banks_client,
+            &[instruction],
+            payer,
+            &signers,
+            recent_blockhash,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+        );
+    }
+
+    // none of the rejected instructions moved anything
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        0
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.balance.base, 1000000 * BASE_UNIT);
+    assert_eq!(state.beneficiary, beneficiary.pubkey());
+}
+
+#[tokio::test]
+async fn test_state_versioning() {
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let mut context = program_test.start_with_context().await;
+    let authority = Keypair::from_bytes(&context.payer.to_bytes()).unwrap();
+    let recent_blockhash = context.last_blockhash;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+
+    initialize_pool(
+        &mut context.banks_client,
+        &authority,
+        &[],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut context.banks_client, &pool.state).await;
+    assert_eq!(state.version, State::CURRENT_VERSION);
+    assert!(state.is_initialized());
+
+    // allocated with room to spare, the padding left zeroed
+    let state_account = context
+        .banks_client
+        .get_account(pool.state)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state_account.data.len(), State::ACCOUNT_LEN);
+    assert!(state_account.data[State::LEN..]
+        .iter()
+        .all(|&byte| byte == 0));
+
+    // ----------------------------------------------------------------------------
+    // initializing the same pool again
+
+    let err = process(
+        &mut context.banks_client,
+        &[instruction::initialize_pool(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &spl_token::native_mint::id(),
+            &authority.pubkey(),
+            0,
+            TOKEN_SUPPLY,
+            DECIMALS,
+            QUOTE_UNIT * 2,
+            default_fees(),
+            CurveType::ConstantProduct,
+            Balance::default(),
+            0,
+            &authority.pubkey(),
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::AlreadyInitialized as u32)
+        )
+    );
+
+    let state = get_state(&mut context.banks_client, &pool.state).await;
+    assert_eq!(state.beneficiary, beneficiary.pubkey());
+    assert_eq!(state.balance.quote, QUOTE_UNIT);
+
+    // ----------------------------------------------------------------------------
+    // headers other than the current version are caught before decoding
+
+    let mut state_account = context
+        .banks_client
+        .get_account(pool.state)
+        .await
+        .unwrap()
+        .unwrap();
+
+    for (version, error) in [
+        (
+            State::CURRENT_VERSION + 1,
+            InstructionError::Custom(IWRError::UnsupportedStateVersion as u32),
+        ),
+        (
+            State::UNINITIALIZED_VERSION,
+            InstructionError::UninitializedAccount,
+        ),
+    ] {
+        state_account.data[0] = version;
+        context.set_account(&pool.state, &state_account.clone().into());
+
+        let err = process(
+            &mut context.banks_client,
+            &[instruction::set_beneficiary(
+                &authority.pubkey(),
+                &pool.state,
+                &Pubkey::new_unique(),
+            )],
+            &authority,
+            &[],
+            recent_blockhash,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, TransactionError::InstructionError(0, error));
+    }
+}
+
+#[tokio::test]
+async fn test_state_migration() {
+    // a pool as written by the first release: its authority seeded it with `QUOTE_UNIT`
+    // and 1000000 tokens, after which the customer bought for `QUOTE_UNIT / 4`
+    let authority = keypair_from_seed(&[1; 32]).unwrap();
+    let customer = keypair_from_seed(&[2; 32]).unwrap();
+    let impostor = Keypair::new();
+    let pool = get_pool_addresses(&authority.pubkey(), LEGACY_POOL_INDEX);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+    let state_data: &[u8] = include_bytes!("fixtures/v0_state.bin");
+    let expected_state = StateV0::try_from_slice(state_data)
+        .unwrap()
+        .into_current(DECIMALS);
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &authority, QUOTE_UNIT as u64);
+    add_funded_account(&mut program_test, &customer, QUOTE_UNIT as u64);
+    add_funded_account(&mut program_test, &impostor, QUOTE_UNIT as u64);
+
+    for (address, data, owner) in [
+        (pool.state, state_data, id()),
+        (
+            pool.mint,
+            &include_bytes!("fixtures/v0_mint.bin")[..],
+            spl_token::id(),
+        ),
+        (
+            pool.token_pool,
+            &include_bytes!("fixtures/v0_token_pool.bin")[..],
+            spl_token::id(),
+        ),
+        (
+            customer_token_address,
+            &include_bytes!("fixtures/v0_customer_token.bin")[..],
+            spl_token::id(),
+        ),
+    ] {
+        program_test.add_account(
+            address,
+            Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data: data.to_vec(),
+                owner,
+                ..Account::default()
+            },
+        );
+    }
+
+    // the native pool held its rent on top of the quote
+    program_test.add_account(
+        pool.native_pool,
+        Account {
+            lamports: Rent::default().minimum_balance(0) + expected_state.balance.quote as u64,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+
+    // a current state sized exactly, as written before the padding
+    let unpadded_creator = Keypair::new();
+    add_funded_account(&mut program_test, &unpadded_creator, QUOTE_UNIT as u64);
+    let unpadded_state_address = get_state_address(&unpadded_creator.pubkey(), 0);
+    let unpadded_state = State {
+        creator: unpadded_creator.pubkey(),
+        pool_index: 0,
+        authority: unpadded_creator.pubkey(),
+        ..expected_state.clone()
+    };
+    let mut unpadded_data = vec![0; State::LEN];
+    unpadded_state.pack(&mut unpadded_data).unwrap();
+    program_test.add_account(
+        unpadded_state_address,
+        Account {
+            lamports: Rent::default().minimum_balance(State::LEN),
+            data: unpadded_data,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+
+    // and one sized before the oracle was appended, under the same version
+    let pre_oracle_creator = Keypair::new();
+    add_funded_account(&mut program_test, &pre_oracle_creator, QUOTE_UNIT as u64);
+    let pre_oracle_state_address = get_state_address(&pre_oracle_creator.pubkey(), 0);
+    let pre_oracle_state = State {
+        creator: pre_oracle_creator.pubkey(),
+        pool_index: 0,
+        authority: pre_oracle_creator.pubkey(),
+        ..expected_state.clone()
+    };
+    let mut pre_oracle_data = vec![0; State::LEN];
+    pre_oracle_state.pack(&mut pre_oracle_data).unwrap();
+    pre_oracle_data.truncate(State::LEN - Observation::LEN);
+    program_test.add_account(
+        pre_oracle_state_address,
+        Account {
+            lamports: Rent::default().minimum_balance(pre_oracle_data.len()),
+            data: pre_oracle_data,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+
+    // a state account of no known layout
+    let unknown_state_address = get_state_address(&impostor.pubkey(), 0);
+    program_test.add_account(
+        unknown_state_address,
+        Account {
+            lamports: Rent::default().minimum_balance(100),
+            data: vec![7; 100],
+            owner: id(),
+            ..Account::default()
+        },
+    );
+
+    let (mut banks_client, _, recent_blockhash) = program_test.start().await;
+
+    assert_eq!(expected_state.authority, authority.pubkey());
+    assert_eq!(expected_state.pool_index, LEGACY_POOL_INDEX);
+    assert_eq!(expected_state.mint_authority, pool.mint);
+    assert_eq!(expected_state.base_pool_authority, pool.token_pool);
+    assert_eq!(expected_state.quote_pool_authority, pool.native_pool);
+    assert_eq!(expected_state.lp_supply, expected_state.balance.quote);
+
+    // the mint keeps its decimals, the balance its scale of a whole token per raw one
+    assert_eq!(expected_state.base_decimals, DECIMALS);
+    assert_eq!(expected_state.base_scale(), BASE_UNIT);
+
+    // the old layout can't be used until it's migrated
+    let sell_instruction = |base_amount| {
+        instruction::sell(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &authority.pubkey(),
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer.pubkey(),
+            base_amount,
+        )
+    };
+
+    process(
+        &mut banks_client,
+        &[sell_instruction(BASE_UNIT * 500)],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::migrate_state(
+            &impostor.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.lp_mint,
+        )],
+        &impostor,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::migrate_state(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.lp_mint,
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // grown to the padded size and still rent-exempt
+    let state_account = banks_client.get_account(pool.state).await.unwrap().unwrap();
+    assert_eq!(state_account.data.len(), State::ACCOUNT_LEN);
+    assert!(Rent::default().is_exempt(state_account.lamports, state_account.data.len()));
+    assert_eq!(
+        get_state(&mut banks_client, &pool.state).await,
+        expected_state
+    );
+
+    // with an LP mint of its own, none of its shares minted
+    let lp_mint_account = banks_client
+        .get_account(pool.lp_mint)
+        .await
+        .unwrap()
+        .unwrap();
+    let lp_mint = spl_token::state::Mint::unpack(&lp_mint_account.data).unwrap();
+    assert_eq!(lp_mint.supply, 0);
+    assert_eq!(lp_mint.mint_authority, Some(pool.lp_mint).into());
+
+    // migrating twice is refused
+    let err = process(
+        &mut banks_client,
+        &[instruction::migrate_state(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.lp_mint,
+        )],
+        &impostor,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::AlreadyMigrated as u32)
+        )
+    );
+
+    // the customer trades their tokens against the reserves the first release left
+    let customer_tokens = get_token_balance(&mut banks_client, &customer_token_address).await;
+    assert_eq!(customer_tokens, 200000);
+
+    let expected_sale = simulate_quote(
+        &mut banks_client,
+        &customer,
+        &pool.state,
+        &pool.native_pool,
+        Side::Sell,
+        BASE_UNIT * 1000,
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    process(
+        &mut banks_client,
+        &[sell_instruction(BASE_UNIT * 1000)],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &customer_token_address).await,
+        customer_tokens - 1000
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        state.balance.quote,
+        expected_state.balance.quote - expected_sale.amount_out - expected_sale.fee
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::buy(
+            &authority.pubkey(),
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &authority.pubkey(),
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer.pubkey(),
+            QUOTE_UNIT / 10,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // the native pool still holds its rent and every lamport of quote on record
+    let state = get_state(&mut banks_client, &pool.state).await;
+    let native_pool_account = banks_client
+        .get_account(pool.native_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        native_pool_account.lamports,
+        Rent::default().minimum_balance(0) + state.balance.quote as u64
+    );
+
+    // ----------------------------------------------------------------------------
+    // growing a current state to the padded size
+
+    process(
+        &mut banks_client,
+        &[instruction::migrate_state(
+            &unpadded_creator.pubkey(),
+            &unpadded_state_address,
+            &get_mint_address(&unpadded_creator.pubkey(), 0),
+            &get_lp_mint_address(&unpadded_creator.pubkey(), 0),
+        )],
+        &unpadded_creator,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state_account = banks_client
+        .get_account(unpadded_state_address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state_account.data.len(), State::ACCOUNT_LEN);
+    assert!(Rent::default().is_exempt(state_account.lamports, state_account.data.len()));
+    assert_eq!(
+        get_state(&mut banks_client, &unpadded_state_address).await,
+        unpadded_state
+    );
+
+    // the state from before the oracle can't be used until it's grown
+    let err = process(
+        &mut banks_client,
+        &[instruction::set_beneficiary(
+            &pre_oracle_creator.pubkey(),
+            &pre_oracle_state_address,
+            &impostor.pubkey(),
+        )],
+        &pre_oracle_creator,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::UnsupportedStateVersion as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::migrate_state(
+            &pre_oracle_creator.pubkey(),
+            &pre_oracle_state_address,
+            &get_mint_address(&pre_oracle_creator.pubkey(), 0),
+            &get_lp_mint_address(&pre_oracle_creator.pubkey(), 0),
+        )],
+        &pre_oracle_creator,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // with no observation recorded yet
+    let state = get_state(&mut banks_client, &pre_oracle_state_address).await;
+    assert_eq!(state.oracle, Observation::default());
+    assert_eq!(state, pre_oracle_state);
+
+    process(
+        &mut banks_client,
+        &[instruction::set_beneficiary(
+            &pre_oracle_creator.pubkey(),
+            &pre_oracle_state_address,
+            &customer.pubkey(),
+        )],
+        &pre_oracle_creator,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[instruction::migrate_state(
+            &impostor.pubkey(),
+            &unknown_state_address,
+            &get_mint_address(&impostor.pubkey(), 0),
+            &get_lp_mint_address(&impostor.pubkey(), 0),
+        )],
+        &impostor,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::UnsupportedStateVersion as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_quote() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // ----------------------------------------------------------------------------
+    // quoting a buy, then making it
+
+    let quote_amount = QUOTE_UNIT / 10;
+    let buy_quote = simulate_quote(
+        &mut banks_client,
+        &authority,
+        &pool.state,
+        &pool.native_pool,
+        Side::Buy,
+        quote_amount,
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(buy_quote.amount_in, quote_amount);
+    assert_eq!(
+        buy_quote.fee,
+        state.fees.calculate_buy_fee(quote_amount).unwrap()
+    );
+    assert_eq!(
+        buy_quote.spot_price_before,
+        state.curve.spot_price(&state.balance).unwrap()
+    );
+    assert!(buy_quote.spot_price_after > buy_quote.spot_price_before);
+    assert!(buy_quote.price_impact_bps > 0);
+
+    // quoting for real leaves the pool as it was
+    process(
+        &mut banks_client,
+        &[instruction::quote(
+            &pool.state,
+            &pool.native_pool,
+            Side::Buy,
+            quote_amount,
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(get_state(&mut banks_client, &pool.state).await, state);
+
+    let beneficiary_lamports = banks_client
+        .get_balance(beneficiary.pubkey())
+        .await
+        .unwrap();
+
+    process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            quote_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let customer_tokens = get_token_balance(&mut banks_client, &customer_token_address).await;
+    assert_eq!(customer_tokens as u128 * BASE_SCALE, buy_quote.amount_out);
+    assert_eq!(
+        banks_client
+            .get_balance(beneficiary.pubkey())
+            .await
+            .unwrap()
+            - beneficiary_lamports,
+        buy_quote.fee as u64
+    );
+
+    // ----------------------------------------------------------------------------
+    // quoting a sell of everything bought, then making it
+
+    let sell_quote = simulate_quote(
+        &mut banks_client,
+        &authority,
+        &pool.state,
+        &pool.native_pool,
+        Side::Sell,
+        customer_tokens as u128 * BASE_SCALE + 1,
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // fractions of a token are never sold
+    assert_eq!(sell_quote.amount_in, customer_tokens as u128 * BASE_SCALE);
+    assert_eq!(sell_quote.spot_price_before, buy_quote.spot_price_after);
+    assert!(sell_quote.spot_price_after < sell_quote.spot_price_before);
+    assert!(sell_quote.price_impact_bps > 0);
+
+    let customer_lamports = banks_client.get_balance(customer.pubkey()).await.unwrap();
+
+    process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            sell_quote.amount_in,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        banks_client.get_balance(customer.pubkey()).await.unwrap() - customer_lamports,
+        sell_quote.amount_out as u64
+    );
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(
+        state.curve.spot_price(&state.balance).unwrap(),
+        sell_quote.spot_price_after
+    );
+
+    // ----------------------------------------------------------------------------
+    // quotes fail the same way trades would
+
+    process(
+        &mut banks_client,
+        &[instruction::halt_buys_only(
+            &authority.pubkey(),
+            &pool.state,
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = simulate_quote(
+        &mut banks_client,
+        &customer,
+        &pool.state,
+        &pool.native_pool,
+        Side::Buy,
+        quote_amount,
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::BuysHalted as u32)
+        )
+    );
+
+    simulate_quote(
+        &mut banks_client,
+        &authority,
+        &pool.state,
+        &pool.native_pool,
+        Side::Sell,
+        BASE_UNIT,
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // selling less than a raw token sells nothing
+    let err = simulate_quote(
+        &mut banks_client,
+        &authority,
+        &pool.state,
+        &pool.native_pool,
+        Side::Sell,
+        BASE_SCALE - 1,
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::AmountTooSmall as u32)
+        )
+    );
+
+    // the payout is checked against the pool's own quote
+    let err = simulate_quote(
+        &mut banks_client,
+        &authority,
+        &pool.state,
+        &customer.pubkey(),
+        Side::Sell,
+        BASE_UNIT,
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAddress as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_price_oracle() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let mut context = program_test.start_with_context().await;
+    let authority = Keypair::from_bytes(&context.payer.to_bytes()).unwrap();
+    let recent_blockhash = context.last_blockhash;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+
+    initialize_pool(
+        &mut context.banks_client,
+        &authority,
+        &[&customer],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let state = get_state(&mut context.banks_client, &pool.state).await;
+    assert_eq!(state.oracle.timestamp, clock.unix_timestamp);
+    assert_eq!(state.oracle.price_cumulative, 0);
+
+    // ----------------------------------------------------------------------------
+    // creating the ring buffer, once
+
+    process(
+        &mut context.banks_client,
+        &[instruction::initialize_oracle(
+            &customer.pubkey(),
+            &pool.state,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = process(
+        &mut context.banks_client,
+        &[instruction::initialize_oracle(
+            &authority.pubkey(),
+            &pool.state,
+        )],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::AlreadyInitialized as u32)
+        )
+    );
+
+    let oracle_account = context
+        .banks_client
+        .get_account(pool.oracle)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(oracle_account.owner, id());
+    assert_eq!(oracle_account.data.len(), Oracle::LEN);
+
+    let oracle = Oracle::unpack(&oracle_account.data).unwrap();
+    assert_eq!(oracle.len, 1);
+    assert_eq!(oracle.latest(), Some(&state.oracle));
+
+    // ----------------------------------------------------------------------------
+    // swapping at later times, each weighing the price that held until then
+
+    let mut prices = vec![];
+
+    for (elapsed, instruction) in [
+        (
+            100,
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                QUOTE_UNIT,
+            ),
+        ),
+        (
+            50,
+            sell(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                1000 * BASE_UNIT,
+            ),
+        ),
+        (
+            0,
+            sell(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                2000 * BASE_UNIT,
+            ),
+        ),
+    ] {
+        let state = get_state(&mut context.banks_client, &pool.state).await;
+        prices.push((elapsed, state.curve.spot_price(&state.balance).unwrap()));
+
+        let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+        clock.unix_timestamp = state.oracle.timestamp + elapsed;
+        context.set_sysvar(&clock);
+
+        process(
+            &mut context.banks_client,
+            &[instruction],
+            &customer,
+            &[],
+            recent_blockhash,
+        )
+        .await
+        .unwrap();
+
+        let swapped_state = get_state(&mut context.banks_client, &pool.state).await;
+        assert_eq!(swapped_state.oracle.timestamp, clock.unix_timestamp);
+        assert_eq!(
+            swapped_state.oracle.price_cumulative,
+            state.oracle.price_cumulative + prices.last().unwrap().1 * elapsed as u128
+        );
+    }
+
+    let state = get_state(&mut context.banks_client, &pool.state).await;
+    let oracle = Oracle::unpack(
+        &context
+            .banks_client
+            .get_account(pool.oracle)
+            .await
+            .unwrap()
+            .unwrap()
+            .data,
+    )
+    .unwrap();
+
+    // the swap in the same second as the one before it isn't recorded again
+    assert_eq!(oracle.len, 3);
+    assert_eq!(oracle.latest(), Some(&state.oracle));
+
+    let observations: Vec<_> = oracle.iter().collect();
+    let expected_twap = (prices[0].1 * 100 + prices[1].1 * 50) / 150;
+    assert_eq!(observations[2].twap(observations[0]), Some(expected_twap));
+    assert_eq!(observations[2].twap(observations[1]), Some(prices[1].1));
+    assert_eq!(
+        oracle
+            .observation_before(observations[1].timestamp + 10)
+            .unwrap(),
+        observations[1]
+    );
+}
+
+#[tokio::test]
+async fn test_skim() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let quote_destination = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+    add_funded_account(&mut program_test, &quote_destination, QUOTE_UNIT as u64);
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+    let base_destination_address = get_associated_token_address(&authority.pubkey(), &pool.mint);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer, &authority],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let skim = |authority: &Pubkey| {
+        instruction::skim(
+            authority,
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &base_destination_address,
+            &quote_destination.pubkey(),
+        )
+    };
+
+    // a buy, then tokens and lamports sent to the pools directly
+    process(
+        &mut banks_client,
+        &[
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                QUOTE_UNIT / 3,
+            ),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &customer_token_address,
+                &pool.token_pool,
+                &customer.pubkey(),
+                &[],
+                5,
+            )
+            .unwrap(),
+            solana_sdk::system_instruction::transfer(&customer.pubkey(), &pool.native_pool, 12345),
+        ],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let state = get_state(&mut banks_client, &pool.state).await;
+    assert_eq!(state.base_dust, 0);
+
+    // ----------------------------------------------------------------------------
+    // skimming
+
+    let err = process(
+        &mut banks_client,
+        &[skim(&customer.pubkey())],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    let token_pool_tokens = get_token_balance(&mut banks_client, &pool.token_pool).await;
+    let native_pool_lamports = banks_client.get_balance(pool.native_pool).await.unwrap();
+
+    process(
+        &mut banks_client,
+        &[skim(&authority.pubkey())],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // only the surplus leaves, the reserves stay
+    assert_eq!(
+        get_token_balance(&mut banks_client, &base_destination_address).await,
+        5
+    );
+    assert_eq!(
+        banks_client
+            .get_balance(quote_destination.pubkey())
+            .await
+            .unwrap(),
+        QUOTE_UNIT as u64 + 12345
+    );
+    assert_eq!(
+        get_token_balance(&mut banks_client, &pool.token_pool).await,
+        token_pool_tokens - 5
+    );
+    assert_eq!(
+        banks_client.get_balance(pool.native_pool).await.unwrap(),
+        native_pool_lamports - 12345
+    );
+    assert_eq!(get_state(&mut banks_client, &pool.state).await, state);
+
+    // with nothing left to skim, nothing moves
+    process(
+        &mut banks_client,
+        &[skim(&authority.pubkey())],
+        &customer,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut banks_client, &base_destination_address).await,
+        5
+    );
+    assert_eq!(
+        banks_client
+            .get_balance(quote_destination.pubkey())
+            .await
+            .unwrap(),
+        QUOTE_UNIT as u64 + 12345
+    );
+
+    // the reserves still cover selling everything back
+    let customer_tokens = get_token_balance(&mut banks_client, &customer_token_address).await;
+
+    process(
+        &mut banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            customer_tokens as u128 * BASE_SCALE,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_close_pool() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+    let destination = Pubkey::new_unique();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let (mut banks_client, authority, recent_blockhash) = program_test.start().await;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+    let customer_lp_address = get_associated_token_address(&customer.pubkey(), &pool.lp_mint);
+
+    initialize_pool(
+        &mut banks_client,
+        &authority,
+        &[&customer, &authority],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    process(
+        &mut banks_client,
+        &[
+            buy(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                QUOTE_UNIT,
+            ),
+            instruction::initialize_oracle(&customer.pubkey(), &pool.state),
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &customer.pubkey(),
+                &customer.pubkey(),
+                &pool.lp_mint,
+                &spl_token::id(),
+            ),
+            instruction::add_liquidity(
+                &pool.state,
+                &pool.token_pool,
+                &pool.native_pool,
+                &pool.lp_mint,
+                &customer.pubkey(),
+                &customer_token_address,
+                &customer_lp_address,
+                &customer.pubkey(),
+                QUOTE_UNIT / 10,
+                BASE_UNIT * 1000000,
+            ),
+        ],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let close_pool = |authority: &Pubkey| {
+        instruction::close_pool(
+            authority,
+            &pool.state,
+            &pool.mint,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &destination,
+            &destination,
+        )
+    };
+
+    // ----------------------------------------------------------------------------
+    // closing
+
+    let err = process(
+        &mut banks_client,
+        &[close_pool(&authority.pubkey())],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::PoolNotHalted as u32)
+        )
+    );
+
+    process(
+        &mut banks_client,
+        &[instruction::pause(&authority.pubkey(), &pool.state)],
+        &authority,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let err = process(
+        &mut banks_client,
+        &[close_pool(&customer.pubkey())],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::IncorrectAuthority as u32)
+        )
+    );
+
+    // the customer's liquidity has to be withdrawn first, paid by the beneficiary to keep
+    // the transaction unique
+    let err = process(
+        &mut banks_client,
+        &[close_pool(&authority.pubkey())],
+        &beneficiary,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::LiquidityOutstanding as u32)
+        )
+    );
+
+    let lp_amount = get_token_balance(&mut banks_client, &customer_lp_address).await;
+
+    process(
+        &mut banks_client,
+        &[instruction::remove_liquidity(
+            &pool.state,
+            &pool.token_pool,
+            &pool.native_pool,
+            &pool.lp_mint,
+            &customer.pubkey(),
+            &customer_token_address,
+            &customer_lp_address,
+            &customer.pubkey(),
+            lp_amount as u128,
+            0,
+            0,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // before graduation the reserves back the tokens sold, so those have to come back too
+    let err = process(
+        &mut banks_client,
+        &[close_pool(&authority.pubkey())],
+        &customer,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::TokensCirculating as u32)
+        )
+    );
+
+    let customer_tokens = get_token_balance(&mut banks_client, &customer_token_address).await;
+
+    process(
+        &mut banks_client,
+        &[
+            instruction::resume(&authority.pubkey(), &pool.state),
+            sell(
+                &authority.pubkey(),
+                &beneficiary.pubkey(),
+                &customer.pubkey(),
+                customer_tokens as u128 * BASE_SCALE,
+            ),
+        ],
+        &customer,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let mut lamports = 0;
+    for address in [pool.state, pool.token_pool, pool.native_pool, pool.oracle] {
+        lamports += banks_client.get_balance(address).await.unwrap();
+    }
+
+    process(
+        &mut banks_client,
+        &[
+            instruction::pause(&authority.pubkey(), &pool.state),
+            close_pool(&authority.pubkey()),
+        ],
+        &customer,
+        &[&authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // rent and quote alike end up with the destination, every token is burnt
+    assert_eq!(
+        banks_client.get_balance(destination).await.unwrap(),
+        lamports
+    );
+    for address in [pool.state, pool.token_pool, pool.native_pool, pool.oracle] {
+        assert_eq!(banks_client.get_account(address).await.unwrap(), None);
+    }
+
+    let mint = banks_client.get_account(pool.mint).await.unwrap().unwrap();
+    assert_eq!(
+        spl_token::state::Mint::unpack(&mint.data).unwrap().supply,
+        0
+    );
+
+    // the pool is gone for good
+    let err = process(
+        &mut banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT / 10,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await;
+    assert!(err.is_err());
+}
+
+#[tokio::test]
+async fn test_native_pool_rent_floor() {
+    let customer = Keypair::new();
+    let beneficiary = Keypair::new();
+
+    let mut program_test = program_test();
+    add_funded_account(&mut program_test, &customer, (QUOTE_UNIT * 5) as u64);
+    add_funded_account(&mut program_test, &beneficiary, QUOTE_UNIT as u64);
+
+    let mut context = program_test.start_with_context().await;
+    let authority = Keypair::from_bytes(&context.payer.to_bytes()).unwrap();
+    let recent_blockhash = context.last_blockhash;
+    let pool = get_pool_addresses(&authority.pubkey(), 0);
+    let customer_token_address = get_associated_token_address(&customer.pubkey(), &pool.mint);
+    let native_pool_rent = Rent::default().minimum_balance(0);
+
+    initialize_pool(
+        &mut context.banks_client,
+        &authority,
+        &[&customer, &authority],
+        default_fees(),
+        &beneficiary.pubkey(),
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    process(
+        &mut context.banks_client,
+        &[buy(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            QUOTE_UNIT,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    let customer_tokens =
+        get_token_balance(&mut context.banks_client, &customer_token_address).await;
+    let base_amount = customer_tokens as u128 * BASE_SCALE;
+    let quote = simulate_quote(
+        &mut context.banks_client,
+        &customer,
+        &pool.state,
+        &pool.native_pool,
+        Side::Sell,
+        base_amount,
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let quote_return = (quote.amount_out + quote.fee) as u64;
+
+    // ----------------------------------------------------------------------------
+    // selling against a native pool short of its books
+
+    // the lamports cover the payout, only not on top of the rent
+    let mut native_pool_account = context
+        .banks_client
+        .get_account(pool.native_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    native_pool_account.lamports = quote_return + native_pool_rent - 1;
+    context.set_account(&pool.native_pool, &native_pool_account.clone().into());
+
+    let err = process(
+        &mut context.banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            base_amount,
+        )],
+        &customer,
+        &[],
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IWRError::NotEnoughLamports as u32)
+        )
+    );
+
+    // quoting the sell refuses it just the same
+    let quote_err = simulate_quote(
+        &mut context.banks_client,
+        &customer,
+        &pool.state,
+        &pool.native_pool,
+        Side::Sell,
+        base_amount,
+        recent_blockhash,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(quote_err, err);
+
+    // one more lamport and the pool is left with exactly its rent
+    native_pool_account.lamports += 1;
+    context.set_account(&pool.native_pool, &native_pool_account.into());
+
+    let quote_after_top_up = simulate_quote(
+        &mut context.banks_client,
+        &customer,
+        &pool.state,
+        &pool.native_pool,
+        Side::Sell,
+        base_amount,
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(quote_after_top_up, quote);
+
+    process(
+        &mut context.banks_client,
+        &[sell(
+            &authority.pubkey(),
+            &beneficiary.pubkey(),
+            &customer.pubkey(),
+            base_amount,
+        )],
+        &authority,
+        &[&customer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        context
+            .banks_client
+            .get_balance(pool.native_pool)
+            .await
+            .unwrap(),
+        native_pool_rent
+    );
+}